@@ -0,0 +1,242 @@
+// SPDX-FileCopyrightText: 2024 Huang-Huang Bao
+// SPDX-License-Identifier: MIT
+// SPDX-License-Identifier: Apache-2.0
+use std::io::{self, Write};
+use std::str::FromStr;
+
+const BIN: &str = "rtl8152-led-ctrl";
+
+/// Global options and whether each of them takes a value
+const GLOBAL_OPTIONS: &[(&str, bool)] = &[
+    ("--interface", true),
+    ("--require-class", true),
+    ("-v", false),
+    ("--verbose", false),
+    ("--assume-version", true),
+    ("--no-version-check", false),
+    ("--delay-after-open", true),
+    ("--timeout-ms", true),
+    ("--detach", false),
+    ("--retries", true),
+    ("--help", false),
+];
+
+/// Options of subcommands with a fixed set of values
+const OPTION_VALUES: &[(&str, &[&str])] = &[
+    ("--type", &["pla", "usb"]),
+    ("--width", &["8", "16", "32"]),
+    ("--match", &["all", "any"]),
+    ("--raw-format", &["hex", "dec", "bin"]),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl FromStr for Shell {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "bash" => Ok(Self::Bash),
+            "zsh" => Ok(Self::Zsh),
+            "fish" => Ok(Self::Fish),
+            unknown => Err(format!(
+                "invalid shell {}, one of bash, zsh or fish",
+                unknown
+            )),
+        }
+    }
+}
+
+/// Writes completion script of given shell, completing subcommands in form of
+/// `(name, description)`, global options and values of known options
+pub fn write_completions(
+    out: &mut impl Write,
+    shell: Shell,
+    subcommands: &[(&str, &str)],
+) -> io::Result<()> {
+    match shell {
+        Shell::Bash => write_bash(out, subcommands),
+        Shell::Zsh => write_zsh(out, subcommands),
+        Shell::Fish => write_fish(out, subcommands),
+    }
+}
+
+/// Quotes a word in single quotes for POSIX-like shells
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+fn value_options() -> impl Iterator<Item = &'static str> {
+    GLOBAL_OPTIONS
+        .iter()
+        .filter(|(_, value)| *value)
+        .map(|(opt, _)| *opt)
+}
+
+fn write_bash(out: &mut impl Write, subcommands: &[(&str, &str)]) -> io::Result<()> {
+    let names: Vec<_> = subcommands.iter().map(|(name, _)| *name).collect();
+    let globals: Vec<_> = GLOBAL_OPTIONS.iter().map(|(opt, _)| *opt).collect();
+
+    writeln!(out, "# bash completion for {}", BIN)?;
+    writeln!(out, "_rtl8152_led_ctrl() {{")?;
+    writeln!(out, "    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\"")?;
+    writeln!(out, "    local prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"")?;
+    writeln!(out, "    case \"$prev\" in")?;
+    for (opt, values) in OPTION_VALUES {
+        writeln!(
+            out,
+            "        {}) COMPREPLY=($(compgen -W {} -- \"$cur\")); return ;;",
+            opt,
+            quote(&values.join(" "))
+        )?;
+    }
+    writeln!(
+        out,
+        "        {}) return ;;",
+        value_options().collect::<Vec<_>>().join("|")
+    )?;
+    writeln!(out, "    esac")?;
+    writeln!(out, "    local i")?;
+    writeln!(out, "    for ((i = 1; i < COMP_CWORD; i++)); do")?;
+    writeln!(out, "        case \"${{COMP_WORDS[i]}}\" in")?;
+    writeln!(out, "            {}) return ;;", names.join("|"))?;
+    writeln!(out, "        esac")?;
+    writeln!(out, "    done")?;
+    writeln!(
+        out,
+        "    COMPREPLY=($(compgen -W {} -- \"$cur\"))",
+        quote(&[names, globals].concat().join(" "))
+    )?;
+    writeln!(out, "}}")?;
+    writeln!(out, "complete -o default -F _rtl8152_led_ctrl {}", BIN)?;
+    Ok(())
+}
+
+fn write_zsh(out: &mut impl Write, subcommands: &[(&str, &str)]) -> io::Result<()> {
+    writeln!(out, "#compdef {}", BIN)?;
+    writeln!(out, "_rtl8152_led_ctrl() {{")?;
+    writeln!(out, "    local -a subcommands")?;
+    writeln!(out, "    subcommands=(")?;
+    for (name, desc) in subcommands {
+        let desc = desc.replace(':', r"\:");
+        writeln!(out, "        {}", quote(&format!("{}:{}", name, desc)))?;
+    }
+    writeln!(out, "    )")?;
+    writeln!(out, "    case \"${{words[CURRENT-1]}}\" in")?;
+    for (opt, values) in OPTION_VALUES {
+        writeln!(
+            out,
+            "        {}) compadd -- {}; return ;;",
+            opt,
+            values.join(" ")
+        )?;
+    }
+    writeln!(
+        out,
+        "        {}) return ;;",
+        value_options().collect::<Vec<_>>().join("|")
+    )?;
+    writeln!(out, "    esac")?;
+    writeln!(out, "    local word")?;
+    writeln!(out, "    for word in \"${{(@)words[2,CURRENT-1]}}\"; do")?;
+    writeln!(
+        out,
+        "        if (( ${{subcommands[(I)${{(b)word}}:*]}} )); then"
+    )?;
+    writeln!(out, "            _files; return")?;
+    writeln!(out, "        fi")?;
+    writeln!(out, "    done")?;
+    writeln!(out, "    _describe subcommand subcommands")?;
+    writeln!(
+        out,
+        "    compadd -- {}",
+        GLOBAL_OPTIONS
+            .iter()
+            .map(|(opt, _)| *opt)
+            .collect::<Vec<_>>()
+            .join(" ")
+    )?;
+    writeln!(out, "}}")?;
+    writeln!(out, "if [[ \"${{funcstack[1]}}\" == _{} ]]; then", BIN)?;
+    writeln!(out, "    _rtl8152_led_ctrl \"$@\"")?;
+    writeln!(out, "else")?;
+    writeln!(out, "    compdef _rtl8152_led_ctrl {}", BIN)?;
+    writeln!(out, "fi")?;
+    Ok(())
+}
+
+fn write_fish(out: &mut impl Write, subcommands: &[(&str, &str)]) -> io::Result<()> {
+    // fish does not recognize `'\''` in single quotes, but `\'`
+    let quote = |s: &str| format!("'{}'", s.replace('\\', r"\\").replace('\'', r"\'"));
+
+    writeln!(out, "# fish completion for {}", BIN)?;
+    writeln!(out, "complete -c {} -n __fish_use_subcommand -f", BIN)?;
+    for (name, desc) in subcommands {
+        writeln!(
+            out,
+            "complete -c {} -n __fish_use_subcommand -a {} -d {}",
+            BIN,
+            name,
+            quote(desc)
+        )?;
+    }
+    for (opt, value) in GLOBAL_OPTIONS {
+        writeln!(
+            out,
+            "complete -c {} -n __fish_use_subcommand {}{}",
+            BIN,
+            match opt.strip_prefix("--") {
+                Some(long) => format!("-l {}", long),
+                None => format!("-s {}", opt.trim_start_matches('-')),
+            },
+            if *value { " -r" } else { "" }
+        )?;
+    }
+    for (opt, values) in OPTION_VALUES {
+        writeln!(
+            out,
+            "complete -c {} -n 'not __fish_use_subcommand' -l {} -x -a {}",
+            BIN,
+            opt.trim_start_matches('-'),
+            quote(&values.join(" "))
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TopArgs;
+    use argh::FromArgs;
+
+    #[test]
+    fn global_options_match_top_args() {
+        let help = TopArgs::from_args(&[BIN], &["--help"]).unwrap_err().output;
+        let mut expected: Vec<_> = help
+            .lines()
+            .skip_while(|line| *line != "Options:")
+            .skip(1)
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| line.trim_start().strip_prefix('-'))
+            .flat_map(|line| {
+                let names = line.split("  ").next().unwrap();
+                format!("-{}", names)
+                    .split(", ")
+                    .map(str::to_owned)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let mut actual: Vec<_> = GLOBAL_OPTIONS
+            .iter()
+            .map(|(opt, _)| opt.to_string())
+            .collect();
+        expected.sort();
+        actual.sort();
+        assert_eq!(actual, expected);
+    }
+}
@@ -0,0 +1,152 @@
+// SPDX-FileCopyrightText: 2024 Huang-Huang Bao
+// SPDX-License-Identifier: MIT
+// SPDX-License-Identifier: Apache-2.0
+use std::path::{Path, PathBuf};
+
+use argh::FromArgs;
+use serde::{Deserialize, Serialize};
+
+use crate::led::{LedConfig, LedGlobalConfig};
+use crate::result::{Error, Result, ResultExt};
+use crate::{set_filtered, ArgLink, ArgU32, CmdSet, Options};
+
+with_selectors! {
+    #[derive(FromArgs, PartialEq, Debug)]
+    #[argh(subcommand, name = "apply")]
+    /// Apply LED configuration from TOML file, same as `set` with its options
+    #[argh(example = "{command_name} --product 0bda:8153 --config leds.toml")]
+    pub struct CmdApply {
+        /// TOML file of LED configuration, see README for keys
+        #[argh(option)]
+        config: PathBuf,
+
+        /// dry run, print result LED configuration only
+        #[argh(switch)]
+        dry: bool,
+    }
+}
+
+/// LED configuration file, keys mirror options of `set`, unspecified keys
+/// get the opinionated default like `set` does
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    /// Link speeds in Mbps, e.g. `[10, 100, 1000]`, empty to deactivate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    led0_link: Option<Vec<u16>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    led1_link: Option<Vec<u16>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    led2_link: Option<Vec<u16>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    led0_act: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    led1_act: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    led2_act: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    led0_reverse: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    led1_reverse: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    led2_reverse: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reverse_all: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    act_all: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interval: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duty_cycle: Option<u8>,
+    /// Raw register value, mutually exclusive with other keys
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raw: Option<u32>,
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path).context("reading config file")?;
+        toml::from_str(&content)
+            .map_err(|e| Error::Config(e.to_string()))
+            .context("parsing config file")
+    }
+
+    /// Snapshot of LED fields of `config`, reserved bits are not included
+    pub fn from_led_config(config: &LedGlobalConfig) -> Self {
+        fn links<const I: u8>(led: &LedConfig<I>) -> Option<Vec<u16>> {
+            let links = [(led.link10, 10), (led.link100, 100), (led.link1000, 1000)];
+            Some(
+                links
+                    .into_iter()
+                    .filter_map(|(enabled, speed)| enabled.then_some(speed))
+                    .collect(),
+            )
+        }
+        ConfigFile {
+            led0_link: links(&config.led_0),
+            led1_link: links(&config.led_1),
+            led2_link: links(&config.led_2),
+            led0_act: Some(config.led_0.activity),
+            led1_act: Some(config.led_1.activity),
+            led2_act: Some(config.led_2.activity),
+            led0_reverse: Some(config.led_0.high_active),
+            led1_reverse: Some(config.led_1.high_active),
+            led2_reverse: Some(config.led_2.high_active),
+            reverse_all: None,
+            act_all: Some(config.all_link_activity),
+            interval: Some(config.blink_interval as u8),
+            duty_cycle: Some(config.blink_duty_cycle as u8),
+            raw: None,
+        }
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string(self).map_err(|e| Error::Config(e.to_string()))
+    }
+
+    /// Fills LED options of `cmd` from the file
+    fn apply_to(self, cmd: &mut CmdSet) -> Result<()> {
+        fn link(speeds: Option<Vec<u16>>) -> Result<Option<ArgLink>> {
+            let Some(speeds) = speeds else {
+                return Ok(None);
+            };
+            let mut res = ArgLink::NONE;
+            for speed in speeds {
+                match speed {
+                    10 => res.link10 = true,
+                    100 => res.link100 = true,
+                    1000 => res.link1000 = true,
+                    unknown => {
+                        return Err(Error::Config(format!("invalid link speed {}", unknown)))
+                    }
+                }
+            }
+            Ok(Some(res))
+        }
+        cmd.led0_link = link(self.led0_link)?;
+        cmd.led1_link = link(self.led1_link)?;
+        cmd.led2_link = link(self.led2_link)?;
+        cmd.led0_act = self.led0_act;
+        cmd.led1_act = self.led1_act;
+        cmd.led2_act = self.led2_act;
+        cmd.led0_reverse = self.led0_reverse;
+        cmd.led1_reverse = self.led1_reverse;
+        cmd.led2_reverse = self.led2_reverse;
+        cmd.reverse_all = self.reverse_all;
+        cmd.act_all = self.act_all;
+        cmd.interval = self.interval;
+        cmd.duty_cycle = self.duty_cycle;
+        cmd.raw = self.raw.map(ArgU32);
+        Ok(())
+    }
+}
+
+pub fn handle_cmd_apply(cmd: CmdApply, opts: &Options) -> Result<()> {
+    let config = ConfigFile::load(&cmd.config)?;
+    let mut cmd_set = CmdSet::from_args(&["set"], &[]).expect("empty arguments of set");
+    cmd_set.dry = cmd.dry;
+    config
+        .apply_to(&mut cmd_set)
+        .context("parsing config file")?;
+    set_filtered(cmd_set, &cmd.filter(), opts)
+}
@@ -0,0 +1,155 @@
+// SPDX-FileCopyrightText: 2024 Huang-Huang Bao
+// SPDX-License-Identifier: MIT
+// SPDX-License-Identifier: Apache-2.0
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use argh::FromArgs;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::{cursor, execute, queue, terminal};
+
+use crate::result::{Result, ResultExt};
+use crate::{led, open_ctrl, print_device_line, scan, Options};
+
+with_selectors! {
+    #[derive(FromArgs, PartialEq, Debug)]
+    #[argh(subcommand, name = "dashboard")]
+    /// Live view of devices and LED configuration, press q to quit
+    pub struct CmdDashboard {
+        /// refresh interval in milliseconds, defaults to 1000
+        #[argh(option)]
+        interval_ms: Option<u64>,
+    }
+}
+
+/// Restores terminal on drop
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let guard = TerminalGuard;
+        execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(guard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// A line of the dashboard, `key` identifies the line across refreshes to
+/// highlight changed values
+struct Line {
+    key: String,
+    text: String,
+}
+
+fn collect_lines(cmd: &CmdDashboard, opts: &Options) -> Result<Vec<Line>> {
+    let mut lines = Vec::new();
+    let devices =
+        scan::filter_devices(&cmd.filter(), opts, false).context("enumerating devices")?;
+    for device in devices {
+        let id = format!("{:03}:{:03}", device.bus_number(), device.address());
+        let mut push = |key: &str, text: String| {
+            lines.push(Line {
+                key: format!("{}/{}", id, key),
+                text,
+            })
+        };
+
+        let res = open_ctrl(&device, opts).and_then(|ctrl| {
+            let mut buf = Vec::new();
+            print_device_line(&mut buf, &ctrl)?;
+            let config = led::LedGlobalConfig::read_from(&ctrl)?;
+            Ok((buf, config))
+        });
+        let (buf, config) = match res {
+            Ok(res) => res,
+            Err(e) => {
+                push("error", format!("Bus({}) {}", id, e));
+                continue;
+            }
+        };
+        push(
+            "device",
+            String::from_utf8_lossy(&buf).trim_end().to_string(),
+        );
+        for field in config.fields() {
+            match field.led {
+                Some(i) => push(
+                    &format!("led{}/{}", i, field.name),
+                    format!("  LED {} {}: {}", i, field.name, field.value),
+                ),
+                None => push(field.name, format!("  {}: {}", field.name, field.value)),
+            }
+        }
+    }
+    Ok(lines)
+}
+
+fn render(lines: &[Line], previous: &HashMap<String, String>) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    queue!(
+        stdout,
+        terminal::Clear(terminal::ClearType::All),
+        cursor::MoveTo(0, 0),
+        Print("rtl8152-led-ctrl dashboard, press q to quit"),
+    )?;
+    if lines.is_empty() {
+        queue!(stdout, cursor::MoveTo(0, 2), Print("No device found"))?;
+    }
+    for (row, line) in lines.iter().enumerate() {
+        let changed = previous
+            .get(&line.key)
+            .is_some_and(|text| text != &line.text);
+        queue!(stdout, cursor::MoveTo(0, row as u16 + 2))?;
+        if changed {
+            queue!(
+                stdout,
+                SetAttribute(Attribute::Reverse),
+                Print(&line.text),
+                SetAttribute(Attribute::Reset)
+            )?;
+        } else {
+            queue!(stdout, Print(&line.text))?;
+        }
+    }
+    stdout.flush()
+}
+
+/// Waits for a key quitting dashboard for up to `timeout`
+fn wait_quit(timeout: Duration) -> io::Result<bool> {
+    if !event::poll(timeout)? {
+        return Ok(false);
+    }
+    let Event::Key(key) = event::read()? else {
+        return Ok(false);
+    };
+    let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+    Ok(key.kind == KeyEventKind::Press
+        && (ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)))
+}
+
+pub fn handle_cmd_dashboard(cmd: CmdDashboard, opts: &Options) -> Result<()> {
+    let interval = Duration::from_millis(cmd.interval_ms.unwrap_or(1000));
+    let _guard = TerminalGuard::enter()?;
+
+    let mut previous = HashMap::new();
+    loop {
+        let lines = collect_lines(&cmd, opts)?;
+        render(&lines, &previous)?;
+        previous = lines
+            .into_iter()
+            .map(|line| (line.key, line.text))
+            .collect();
+        if wait_quit(interval)? {
+            return Ok(());
+        }
+    }
+}
@@ -1,6 +1,8 @@
 // SPDX-FileCopyrightText: 2024 Huang-Huang Bao
 // SPDX-License-Identifier: MIT
 // SPDX-License-Identifier: Apache-2.0
+use std::cell::Cell;
+use std::fmt;
 use std::str::FromStr;
 use std::time::Duration;
 
@@ -30,12 +32,73 @@ const BYTE_EN_DWORD: u8 = 0xff;
 const BYTE_EN_WORD: u8 = 0x33;
 const BYTE_EN_BYTE: u8 = 0x11;
 
+const BYTE_EN_MASKS: &[u8] = &[
+    BYTE_EN_DWORD,
+    BYTE_EN_WORD,
+    BYTE_EN_WORD << 2,
+    BYTE_EN_BYTE,
+    BYTE_EN_BYTE << 1,
+    BYTE_EN_BYTE << 2,
+    BYTE_EN_BYTE << 3,
+];
+
 const CTRL_READ_LIMIT: usize = 64;
 const CTRL_WRITE_LIMIT: usize = 512;
 
+const PLA_IDR: u16 = 0xc000;
+const PLA_BACKUP: u16 = 0xd000;
 const PLA_TCR0: u16 = 0xe610;
 const VERSION_MASK: u32 = 0x7cf0;
 
+/// Register ranges (type, first offset, last offset, description) where a
+/// mistyped write may render the device unusable until power cycled, after
+/// register definitions of the r8152 driver
+const RISKY_REGISTERS: &[(RegType, u16, u16, &str)] = &[
+    (RegType::Pla, 0xc000, 0xc007, "MAC address"),
+    (
+        RegType::Pla,
+        0xe610,
+        0xe613,
+        "transmit configuration and chip version",
+    ),
+    (
+        RegType::Pla,
+        0xe810,
+        0xe813,
+        "command register, may reset the NIC",
+    ),
+    (RegType::Pla, 0xe86c, 0xe86f, "PHY access window"),
+    (
+        RegType::Pla,
+        0xf800,
+        0xffff,
+        "MCU firmware patch and breakpoint",
+    ),
+    (RegType::Usb, 0xd800, 0xd80f, "power management"),
+    (
+        RegType::Usb,
+        0xf800,
+        0xffff,
+        "MCU firmware patch and breakpoint",
+    ),
+];
+
+/// Describes the register at offset if writing to it is risky
+pub fn risky_register(ty: RegType, offset: u16) -> Option<&'static str> {
+    RISKY_REGISTERS
+        .iter()
+        .find(|&&(t, first, last, _)| t == ty && (first..=last).contains(&offset))
+        .map(|&(_, _, _, what)| what)
+}
+
+/// Delays before re-reading version of a device reporting unknown version,
+/// some devices return garbage on first reads right after enumeration
+const VERSION_PROBE_DELAYS_MS: &[u64] = &[10, 20, 40];
+
+/// Delay before the first retry of a failed control transfer, doubled on
+/// each further retry
+const RETRY_BACKOFF_MS: u64 = 10;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RegType {
     Usb,
@@ -64,9 +127,25 @@ impl FromStr for RegType {
     }
 }
 
-pub struct CtrlDevice<T: UsbContext> {
-    handle: rusb::DeviceHandle<T>,
+pub struct CtrlDevice<H: UsbTransfer = rusb::DeviceHandle<rusb::GlobalContext>> {
+    handle: H,
     timeout: Duration,
+    stats: Cell<TransferStats>,
+    assumed_version: Option<Version>,
+    retries: u32,
+    /// Interface to release on drop
+    claimed: Option<u8>,
+    /// Interfaces to reattach kernel drivers to on drop
+    detached: Vec<u8>,
+}
+
+/// Numbers of control transfers issued, for diagnostics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransferStats {
+    pub reads: u64,
+    pub writes: u64,
+    /// Transfers retried after transient errors, included in reads and writes
+    pub retries: u64,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -77,11 +156,28 @@ enum Align {
 }
 
 impl Align {
-    const fn is_aligned(self, offset: usize) -> bool {
+    const fn size(self) -> usize {
         match self {
-            // Align::Byte => true,
-            Align::Word => offset % 2 == 0,
-            Align::Dword => offset % 4 == 0,
+            // Align::Byte => 1,
+            Align::Word => 2,
+            Align::Dword => 4,
+        }
+    }
+
+    const fn is_aligned(self, offset: usize) -> bool {
+        offset % self.size() == 0
+    }
+
+    /// Checks both start offset and length of access are aligned
+    fn check(self, offset: u16, len: usize) -> Result<()> {
+        if self.is_aligned(offset as _) && self.is_aligned(len) {
+            Ok(())
+        } else {
+            Err(Error::Align {
+                required: self.size(),
+                offset,
+                len,
+            })
         }
     }
 }
@@ -91,18 +187,172 @@ const fn dword_align(offset: u16) -> u16 {
 }
 
 fn check_bound(offset: u16, data: &[u8]) -> Result<()> {
-    let align = Align::Dword;
-    if !align.is_aligned(offset as _) || !align.is_aligned(data.len()) {
-        return Err(Error::Align);
-    }
-    let end_offset = offset as usize + data.len();
-    if end_offset > u16::MAX as _ {
-        Err(Error::Bound)
+    let len = data.len();
+    Align::Dword.check(offset, len)?;
+    if offset as usize + len > u16::MAX as _ {
+        Err(Error::Bound { offset, len })
     } else {
         Ok(())
     }
 }
 
+/// Validates byte enable mask, which must select a whole dword, an aligned
+/// word or a single byte.
+fn check_byte_mask(byte_mask: u8) -> Result<()> {
+    if BYTE_EN_MASKS.contains(&byte_mask) {
+        Ok(())
+    } else {
+        Err(Error::ByteMask(byte_mask))
+    }
+}
+
+/// Some chips or firmware reject access to particular registers with
+/// `NotSupported`, which is a hardware limitation rather than a failure
+fn map_usb_error(e: rusb::Error) -> Error {
+    match e {
+        rusb::Error::NotSupported => Error::Unsupported,
+        e => Error::Usb(e),
+    }
+}
+
+/// Interface operations on kernel drivers, abstracted over device handle
+pub trait InterfaceOps {
+    fn kernel_driver_active(&self, interface: u8) -> rusb::Result<bool>;
+    fn detach_kernel_driver(&self, interface: u8) -> rusb::Result<()>;
+    fn attach_kernel_driver(&self, interface: u8) -> rusb::Result<()>;
+    fn release_interface(&self, interface: u8) -> rusb::Result<()>;
+}
+
+impl<T: UsbContext> InterfaceOps for rusb::DeviceHandle<T> {
+    fn kernel_driver_active(&self, interface: u8) -> rusb::Result<bool> {
+        rusb::DeviceHandle::kernel_driver_active(self, interface)
+    }
+
+    fn detach_kernel_driver(&self, interface: u8) -> rusb::Result<()> {
+        rusb::DeviceHandle::detach_kernel_driver(self, interface)
+    }
+
+    fn attach_kernel_driver(&self, interface: u8) -> rusb::Result<()> {
+        rusb::DeviceHandle::attach_kernel_driver(self, interface)
+    }
+
+    fn release_interface(&self, interface: u8) -> rusb::Result<()> {
+        rusb::DeviceHandle::release_interface(self, interface)
+    }
+}
+
+/// Vendor control transfers, abstracted over device handle
+pub trait UsbTransfer: InterfaceOps {
+    fn read_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize>;
+    fn write_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize>;
+}
+
+impl<T: UsbContext> UsbTransfer for rusb::DeviceHandle<T> {
+    fn read_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        rusb::DeviceHandle::read_control(self, request_type, request, value, index, buf, timeout)
+    }
+
+    fn write_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        rusb::DeviceHandle::write_control(self, request_type, request, value, index, buf, timeout)
+    }
+}
+
+/// Detaches kernel drivers bound to given interfaces, returns interfaces
+/// actually detached, i.e. excluding those without a driver bound. On
+/// failure, drivers already detached are reattached.
+pub fn detach_kernel_drivers(handle: &impl InterfaceOps, interfaces: &[u8]) -> Result<Vec<u8>> {
+    let mut detached = Vec::new();
+    for &interface in interfaces {
+        let res = handle.kernel_driver_active(interface).and_then(|active| {
+            if active {
+                handle.detach_kernel_driver(interface)?;
+            }
+            Ok(active)
+        });
+        match res {
+            Ok(true) => detached.push(interface),
+            Ok(false) => {}
+            Err(e) => {
+                attach_kernel_drivers(handle, &detached);
+                return Err(Error::Detach {
+                    interface,
+                    source: e,
+                });
+            }
+        }
+    }
+    Ok(detached)
+}
+
+/// Reattaches kernel drivers to given interfaces, failures are reported to
+/// stderr as the device stays usable
+pub fn attach_kernel_drivers(handle: &impl InterfaceOps, interfaces: &[u8]) {
+    for &interface in interfaces {
+        if let Err(e) = handle.attach_kernel_driver(interface) {
+            eprintln!(
+                "Warning: failed to reattach kernel driver to interface {}: {}",
+                interface, e
+            );
+        }
+    }
+}
+
+/// Releases claimed interface, then reattaches kernel drivers to detached
+/// interfaces, as the kernel refuses to bind a driver to an interface still
+/// claimed by us
+fn release_interfaces(handle: &impl InterfaceOps, claimed: Option<u8>, detached: &[u8]) {
+    if let Some(interface) = claimed {
+        if let Err(e) = handle.release_interface(interface) {
+            eprintln!("Warning: failed to release interface {}: {}", interface, e);
+        }
+    }
+    attach_kernel_drivers(handle, detached);
+}
+
+/// USB port path of device in form of "bus-port.port" as in sysfs, e.g.
+/// "1-1.2", stable as long as the device stays plugged in the same port
+pub fn usb_path<T: UsbContext>(device: &rusb::Device<T>) -> Result<String> {
+    let ports = device
+        .port_numbers()?
+        .iter()
+        .map(|port| port.to_string())
+        .collect::<Vec<_>>()
+        .join(".");
+    Ok(format!("{}-{}", device.bus_number(), ports))
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Version {
     V1,
@@ -124,69 +374,254 @@ pub enum Version {
     Unknown(u16),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Whether the chip links at 1000Mbps, RTL8152(B) are 10/100 only
+    pub link1000: bool,
+    /// Whether the chip has LED mode in LED feature register, RTL8153 and later
+    pub led_feature: bool,
+}
+
 impl Version {
-    fn from_raw(code: u16) -> Self {
+    pub fn capabilities(self) -> Capabilities {
         use Version::*;
-        match code {
-            0x4c00 => V1,
-            0x4c10 => V2,
-            0x5c00 => V3,
-            0x5c10 => V4,
-            0x5c20 => V5,
-            0x5c30 => V6,
-            0x4800 => V7,
-            0x6000 => V8,
-            0x6010 => V9,
-            0x7010 => Test1,
-            0x7020 => V10,
-            0x7030 => V11,
-            0x7400 => V12,
-            0x7410 => V13,
-            0x6400 => V14,
-            0x7420 => V15,
-            code => Unknown(code),
+        let rtl8152 = matches!(self, V1 | V2 | V7);
+        Capabilities {
+            link1000: !rtl8152,
+            led_feature: !rtl8152,
         }
     }
+
+    /// Chip family name, following the init routine the r8152 kernel driver
+    /// picks for the version, "Unknown" for unknown versions
+    pub fn name(self) -> &'static str {
+        use Version::*;
+        match self {
+            V1 | V2 | V7 => "RTL8152",
+            V3 | V4 | V5 | V6 => "RTL8153",
+            V8 | V9 => "RTL8153B",
+            V14 => "RTL8153C",
+            Test1 | V10 | V11 => "RTL8156",
+            V12 | V13 | V15 => "RTL8156B",
+            Unknown(_) => "Unknown",
+        }
+    }
+
+    fn from_raw(code: u16) -> Self {
+        VERSION_CODES
+            .iter()
+            .find(|&&(c, _)| c == code)
+            .map_or(Version::Unknown(code), |&(_, version)| version)
+    }
+}
+
+/// Formats as e.g. "V13 / RTL8156B", or "Unknown(0x7c00)" for unknown versions
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Version::Unknown(code) => write!(f, "Unknown(0x{:04x})", code),
+            version => write!(f, "{:?} / {}", version, version.name()),
+        }
+    }
+}
+
+impl FromStr for Version {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        VERSION_CODES
+            .iter()
+            .map(|&(_, version)| version)
+            .find(|version| format!("{:?}", version).eq_ignore_ascii_case(s))
+            .ok_or_else(|| "unknown version, see `versions` for known versions".to_string())
+    }
+}
+
+/// Raw version codes in `PLA_TCR0` of known versions, other codes are decoded
+/// as [`Version::Unknown`]
+pub const VERSION_CODES: &[(u16, Version)] = &[
+    (0x4c00, Version::V1),
+    (0x4c10, Version::V2),
+    (0x5c00, Version::V3),
+    (0x5c10, Version::V4),
+    (0x5c20, Version::V5),
+    (0x5c30, Version::V6),
+    (0x4800, Version::V7),
+    (0x6000, Version::V8),
+    (0x6010, Version::V9),
+    (0x7010, Version::Test1),
+    (0x7020, Version::V10),
+    (0x7030, Version::V11),
+    (0x7400, Version::V12),
+    (0x7410, Version::V13),
+    (0x6400, Version::V14),
+    (0x7420, Version::V15),
+];
+
+impl<H: UsbTransfer> Drop for CtrlDevice<H> {
+    fn drop(&mut self) {
+        release_interfaces(&self.handle, self.claimed, &self.detached);
+    }
 }
 
-impl<T: UsbContext> CtrlDevice<T> {
-    pub fn new(handle: rusb::DeviceHandle<T>) -> Result<Self> {
-        let ctrl = Self {
+/// Default timeout of each control transfer
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default times to retry a control transfer failed with transient errors
+pub const DEFAULT_RETRIES: u32 = 2;
+
+impl<H: UsbTransfer> CtrlDevice<H> {
+    /// Rejects devices of unknown version, retrying a few times as some
+    /// chips report bogus version right after being plugged in
+    pub fn probe_version(&self) -> Result<()> {
+        let mut delays = VERSION_PROBE_DELAYS_MS.iter();
+        while let Version::Unknown(_) = self.version()? {
+            let Some(&delay) = delays.next() else {
+                return Err(Error::UnknownDevice);
+            };
+            std::thread::sleep(Duration::from_millis(delay));
+        }
+        Ok(())
+    }
+
+    /// Creates controller without rejecting devices of unknown version, for
+    /// gathering data from unsupported chips
+    pub fn new_unchecked(handle: H) -> Self {
+        Self {
             handle,
-            timeout: Duration::from_secs(5),
-        };
-        if let Version::Unknown(_) = ctrl.version()? {
-            Err(Error::UnknownDevice)
-        } else {
-            Ok(ctrl)
+            timeout: DEFAULT_TIMEOUT,
+            stats: Cell::new(TransferStats::default()),
+            assumed_version: None,
+            retries: DEFAULT_RETRIES,
+            claimed: None,
+            detached: Vec::new(),
         }
     }
 
-    pub fn handle(&self) -> &rusb::DeviceHandle<T> {
+    /// Creates controller treating the chip as given version, without
+    /// probing version register
+    pub fn with_version(handle: H, version: Version) -> Self {
+        Self {
+            handle,
+            timeout: DEFAULT_TIMEOUT,
+            stats: Cell::new(TransferStats::default()),
+            assumed_version: Some(version),
+            retries: DEFAULT_RETRIES,
+            claimed: None,
+            detached: Vec::new(),
+        }
+    }
+
+    pub fn handle(&self) -> &H {
         &self.handle
     }
 
+    pub fn stats(&self) -> TransferStats {
+        self.stats.get()
+    }
+
+    /// Timeout of each control transfer
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Sets times to retry a control transfer failed with transient errors,
+    /// i.e. timeout, pipe (stall) and I/O errors
+    pub fn set_retries(&mut self, retries: u32) {
+        self.retries = retries;
+    }
+
+    /// Reattaches kernel drivers to given interfaces on drop, see
+    /// [`detach_kernel_drivers`]
+    pub fn reattach_on_drop(&mut self, interfaces: Vec<u8>) {
+        self.detached = interfaces;
+    }
+
+    /// Releases given claimed interface on drop, before reattaching kernel
+    /// drivers
+    pub fn release_on_drop(&mut self, interface: u8) {
+        self.claimed = Some(interface);
+    }
+
     pub fn version(&self) -> Result<Version> {
+        if let Some(version) = self.assumed_version {
+            return Ok(version);
+        }
         let version = self.read_dword(RegType::Pla, PLA_TCR0)?;
         let version = (version >> 16) & VERSION_MASK;
         Ok(Version::from_raw(version as _))
     }
 
+    /// Reads MAC address currently in use, falls back to the factory MAC
+    /// address backup if the former is not programmed. Returns `None` if
+    /// neither is valid.
+    pub fn mac_address(&self) -> Result<Option<[u8; 6]>> {
+        let mut offsets = vec![PLA_IDR];
+        // RTL8152 V1 has no backup per r8152 driver
+        if self.version()? != Version::V1 {
+            offsets.push(PLA_BACKUP);
+        }
+
+        for offset in offsets {
+            let mut data = [0u8; 8];
+            self.read(RegType::Pla, offset, &mut data)?;
+            let mut mac = [0u8; 6];
+            mac.copy_from_slice(&data[..6]);
+            // neither all zeros nor multicast/broadcast
+            if mac != [0u8; 6] && mac[0] & 1 == 0 {
+                return Ok(Some(mac));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Runs control transfer `f`, retrying with backoff on transient errors,
+    /// other errors and partial transfers are returned as is
+    fn retry_transfer(&self, mut f: impl FnMut() -> rusb::Result<usize>) -> Result<usize> {
+        let mut backoff = Duration::from_millis(RETRY_BACKOFF_MS);
+        let mut left = self.retries;
+        loop {
+            match f() {
+                Err(rusb::Error::Timeout | rusb::Error::Pipe | rusb::Error::Io) if left > 0 => {
+                    left -= 1;
+                    let mut stats = self.stats.get();
+                    stats.retries += 1;
+                    self.stats.set(stats);
+                    std::thread::sleep(backoff);
+                    backoff *= 2;
+                }
+                res => return res.map_err(map_usb_error),
+            }
+        }
+    }
+
     fn read_reg(&self, ty: RegType, offset: u16, byte_mask: u8, data: &mut [u8]) -> Result<()> {
         if data.is_empty() {
             return Ok(());
         }
         check_bound(offset, data)?;
-        let len = self.handle.read_control(
-            RTL8152_REQT_READ,
-            RTL8152_REQ_REGS,
-            offset,
-            ty.to_raw() | byte_mask as u16,
-            data,
-            self.timeout,
-        )?;
+        check_byte_mask(byte_mask)?;
+        let len = self.retry_transfer(|| {
+            let mut stats = self.stats.get();
+            stats.reads += 1;
+            self.stats.set(stats);
+            self.handle.read_control(
+                RTL8152_REQT_READ,
+                RTL8152_REQ_REGS,
+                offset,
+                ty.to_raw() | byte_mask as u16,
+                data,
+                self.timeout,
+            )
+        })?;
         if len != data.len() {
-            Err(Error::Partial)
+            Err(Error::Partial {
+                expected: data.len(),
+                actual: len,
+            })
         } else {
             Ok(())
         }
@@ -197,22 +632,30 @@ impl<T: UsbContext> CtrlDevice<T> {
             return Ok(());
         }
         check_bound(offset, data)?;
-        let len = self.handle.write_control(
-            RTL8152_REQT_WRITE,
-            RTL8152_REQ_REGS,
-            offset,
-            ty.to_raw() | byte_mask as u16,
-            data,
-            self.timeout,
-        )?;
+        check_byte_mask(byte_mask)?;
+        let len = self.retry_transfer(|| {
+            let mut stats = self.stats.get();
+            stats.writes += 1;
+            self.stats.set(stats);
+            self.handle.write_control(
+                RTL8152_REQT_WRITE,
+                RTL8152_REQ_REGS,
+                offset,
+                ty.to_raw() | byte_mask as u16,
+                data,
+                self.timeout,
+            )
+        })?;
         if len != data.len() {
-            Err(Error::Partial)
+            Err(Error::Partial {
+                expected: data.len(),
+                actual: len,
+            })
         } else {
             Ok(())
         }
     }
 
-    #[allow(unused)]
     pub fn read(&self, ty: RegType, offset: u16, data: &mut [u8]) -> Result<()> {
         let mut cur = offset as usize;
         let mut remaining = data;
@@ -251,9 +694,7 @@ impl<T: UsbContext> CtrlDevice<T> {
     }
 
     pub fn read_word(&self, ty: RegType, offset: u16) -> Result<u16> {
-        if !Align::Word.is_aligned(offset as _) {
-            return Err(Error::Align);
-        }
+        Align::Word.check(offset, 2)?;
         let byte_shift = offset & 2;
         let offset = dword_align(offset);
         let byte_mask = BYTE_EN_WORD << byte_shift;
@@ -266,9 +707,7 @@ impl<T: UsbContext> CtrlDevice<T> {
     }
 
     pub fn write_word(&self, ty: RegType, offset: u16, value: u16) -> Result<()> {
-        if !Align::Word.is_aligned(offset as _) {
-            return Err(Error::Align);
-        }
+        Align::Word.check(offset, 2)?;
         let byte_shift = offset & 2;
         let offset = dword_align(offset);
         let byte_mask = BYTE_EN_WORD << byte_shift;
@@ -297,3 +736,256 @@ impl<T: UsbContext> CtrlDevice<T> {
         self.write_reg(ty, offset, byte_mask, &data)
     }
 }
+
+impl<T: UsbContext> CtrlDevice<rusb::DeviceHandle<T>> {
+    /// Identifier of the device stable across replugging and reboots, in form
+    /// of `vid:pid@bus-port.port` with `/serial` appended if the device has a
+    /// serial number which can be read. The identifier changes if the device moves to another
+    /// USB port, or to another bus number after the host controllers got
+    /// enumerated in different order.
+    pub fn stable_id(&self) -> Result<String> {
+        let device = self.handle.device();
+        let desc = device.device_descriptor()?;
+
+        let mut id = format!(
+            "{:04x}:{:04x}@{}",
+            desc.vendor_id(),
+            desc.product_id(),
+            usb_path(&device)?
+        );
+        if desc.serial_number_string_index().is_some() {
+            // an identifier without serial is better than none
+            match self.handle.read_serial_number_string_ascii(&desc) {
+                Ok(serial) if !serial.is_empty() => {
+                    id.push('/');
+                    id.push_str(&serial);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    /// Records interface operations, drivers are bound to `active` interfaces.
+    /// Control reads return dwords queued in `reads`, then zeros.
+    #[derive(Default)]
+    struct MockHandle {
+        active: Vec<u8>,
+        fail_detach: Option<u8>,
+        calls: RefCell<Vec<String>>,
+        reads: RefCell<VecDeque<rusb::Result<u32>>>,
+        /// Timeouts passed to each control transfer
+        timeouts: RefCell<Vec<Duration>>,
+    }
+
+    impl UsbTransfer for MockHandle {
+        fn read_control(
+            &self,
+            _request_type: u8,
+            _request: u8,
+            _value: u16,
+            _index: u16,
+            buf: &mut [u8],
+            timeout: Duration,
+        ) -> rusb::Result<usize> {
+            self.timeouts.borrow_mut().push(timeout);
+            let value = self.reads.borrow_mut().pop_front().unwrap_or(Ok(0))?;
+            let len = buf.len().min(4);
+            buf.fill(0);
+            buf[..len].copy_from_slice(&value.to_le_bytes()[..len]);
+            Ok(buf.len())
+        }
+
+        fn write_control(
+            &self,
+            _request_type: u8,
+            _request: u8,
+            _value: u16,
+            _index: u16,
+            buf: &[u8],
+            timeout: Duration,
+        ) -> rusb::Result<usize> {
+            self.timeouts.borrow_mut().push(timeout);
+            Ok(buf.len())
+        }
+    }
+
+    impl InterfaceOps for MockHandle {
+        fn kernel_driver_active(&self, interface: u8) -> rusb::Result<bool> {
+            Ok(self.active.contains(&interface))
+        }
+
+        fn detach_kernel_driver(&self, interface: u8) -> rusb::Result<()> {
+            if self.fail_detach == Some(interface) {
+                return Err(rusb::Error::Access);
+            }
+            self.calls
+                .borrow_mut()
+                .push(format!("detach {}", interface));
+            Ok(())
+        }
+
+        fn attach_kernel_driver(&self, interface: u8) -> rusb::Result<()> {
+            self.calls
+                .borrow_mut()
+                .push(format!("attach {}", interface));
+            Ok(())
+        }
+
+        fn release_interface(&self, interface: u8) -> rusb::Result<()> {
+            self.calls
+                .borrow_mut()
+                .push(format!("release {}", interface));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn version_names() {
+        for (code, version, name, display) in [
+            (0x4c00, Version::V1, "RTL8152", "V1 / RTL8152"),
+            (0x4800, Version::V7, "RTL8152", "V7 / RTL8152"),
+            (0x5c30, Version::V6, "RTL8153", "V6 / RTL8153"),
+            (0x6010, Version::V9, "RTL8153B", "V9 / RTL8153B"),
+            (0x6400, Version::V14, "RTL8153C", "V14 / RTL8153C"),
+            (0x7010, Version::Test1, "RTL8156", "Test1 / RTL8156"),
+            (0x7030, Version::V11, "RTL8156", "V11 / RTL8156"),
+            (0x7410, Version::V13, "RTL8156B", "V13 / RTL8156B"),
+            (0x7420, Version::V15, "RTL8156B", "V15 / RTL8156B"),
+            (
+                0x7c00,
+                Version::Unknown(0x7c00),
+                "Unknown",
+                "Unknown(0x7c00)",
+            ),
+        ] {
+            let decoded = Version::from_raw(code);
+            assert_eq!(decoded, version, "{:#06x}", code);
+            assert_eq!(decoded.name(), name);
+            assert_eq!(decoded.to_string(), display);
+        }
+    }
+
+    #[test]
+    fn byte_mask_valid() {
+        for mask in [0xff, 0x33, 0x33 << 2, 0x11, 0x11 << 1, 0x11 << 2, 0x11 << 3] {
+            assert!(check_byte_mask(mask).is_ok(), "{:#04x}", mask);
+        }
+    }
+
+    #[test]
+    fn byte_mask_invalid() {
+        // empty, misaligned word, partial lanes and unpaired halves
+        for mask in [0x00, 0x33 << 1, 0x01, 0x0f, 0x31, 0x77, 0xee] {
+            assert!(
+                matches!(check_byte_mask(mask), Err(Error::ByteMask(m)) if m == mask),
+                "{:#04x}",
+                mask
+            );
+        }
+    }
+
+    #[test]
+    fn detach_skips_interfaces_without_driver() {
+        let handle = MockHandle {
+            active: vec![0, 2],
+            ..Default::default()
+        };
+        let detached = detach_kernel_drivers(&handle, &[0, 1, 2]).unwrap();
+        assert_eq!(detached, [0, 2]);
+        assert_eq!(*handle.calls.borrow(), ["detach 0", "detach 2"]);
+    }
+
+    #[test]
+    fn detach_failure_reattaches_detached() {
+        let handle = MockHandle {
+            active: vec![0, 1],
+            fail_detach: Some(1),
+            ..Default::default()
+        };
+        let err = detach_kernel_drivers(&handle, &[0, 1]).unwrap_err();
+        assert!(matches!(err, Error::Detach { interface: 1, .. }));
+        assert_eq!(*handle.calls.borrow(), ["detach 0", "attach 0"]);
+    }
+
+    #[test]
+    fn release_claimed_before_reattach() {
+        let handle = MockHandle::default();
+        release_interfaces(&handle, Some(1), &[0, 1]);
+        assert_eq!(
+            *handle.calls.borrow(),
+            ["release 1", "attach 0", "attach 1"]
+        );
+
+        let handle = MockHandle::default();
+        release_interfaces(&handle, None, &[]);
+        assert!(handle.calls.borrow().is_empty());
+    }
+    #[test]
+    fn timeout_passed_to_transfers() {
+        let mut ctrl = CtrlDevice::new_unchecked(MockHandle::default());
+        assert_eq!(ctrl.timeout(), DEFAULT_TIMEOUT);
+        let timeout = Duration::from_millis(250);
+        ctrl.set_timeout(timeout);
+        assert_eq!(ctrl.timeout(), timeout);
+
+        ctrl.read_dword(RegType::Pla, PLA_TCR0).unwrap();
+        ctrl.write_word(RegType::Pla, 0xdd90, 0x0087).unwrap();
+        assert_eq!(*ctrl.handle().timeouts.borrow(), [timeout, timeout]);
+    }
+    #[test]
+    fn stats_count_probe_and_led_read() {
+        let handle = MockHandle::default();
+        handle
+            .reads
+            .borrow_mut()
+            .extend([Ok(0x7410_0000), Err(rusb::Error::Timeout), Ok(0xe0087)]);
+        let ctrl = CtrlDevice::new_unchecked(handle);
+        ctrl.probe_version().unwrap();
+        let config = crate::led::LedGlobalConfig::read_from(&ctrl).unwrap();
+        assert_eq!(config.to_raw(), 0xe0087);
+        assert_eq!(
+            ctrl.stats(),
+            TransferStats {
+                reads: 3,
+                writes: 0,
+                retries: 1,
+            }
+        );
+    }
+    #[test]
+    fn probe_version_retries_bogus_code() {
+        let handle = MockHandle::default();
+        handle
+            .reads
+            .borrow_mut()
+            .extend([Ok(0x7c00_0000), Ok(0x7410_0000)]);
+        let ctrl = CtrlDevice::new_unchecked(handle);
+        ctrl.probe_version().unwrap();
+        assert_eq!(ctrl.stats().reads, 2);
+        assert!(ctrl.handle().reads.borrow().is_empty());
+    }
+
+    #[test]
+    fn probe_version_gives_up_on_unknown() {
+        let ctrl = CtrlDevice::new_unchecked(MockHandle::default());
+        let start = std::time::Instant::now();
+        assert!(matches!(ctrl.probe_version(), Err(Error::UnknownDevice)));
+        let elapsed = start.elapsed();
+
+        assert_eq!(ctrl.stats().reads, 1 + VERSION_PROBE_DELAYS_MS.len() as u64);
+        let delays = Duration::from_millis(VERSION_PROBE_DELAYS_MS.iter().sum());
+        assert_eq!(delays, Duration::from_millis(70));
+        assert!(elapsed >= delays, "{:?}", elapsed);
+        assert!(elapsed < delays + Duration::from_secs(1), "{:?}", elapsed);
+    }
+}
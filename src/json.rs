@@ -0,0 +1,227 @@
+// SPDX-FileCopyrightText: 2024 Huang-Huang Bao
+// SPDX-License-Identifier: MIT
+// SPDX-License-Identifier: Apache-2.0
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::led::{LedConfig, LedGlobalConfig, RawFields};
+use crate::result::{Result, ResultExt};
+use crate::scan::DeviceInfo;
+
+/// Version of JSON output shape, bumped on incompatible changes, see README
+/// for history
+const SCHEMA_VERSION: u32 = 1;
+
+/// Device entry of `show --json`
+#[derive(Serialize)]
+struct DeviceJson<'a> {
+    schema_version: u32,
+    bus: u8,
+    address: u8,
+    /// Hex string, e.g. "0bda"
+    vid: String,
+    pid: String,
+    manufacturer: &'a str,
+    product: &'a str,
+    serial: &'a str,
+    /// See [`crate::device::CtrlDevice::stable_id`]
+    id: &'a str,
+    version: String,
+    /// Chip family, e.g. "RTL8153B"
+    chip: &'static str,
+    /// Omitted with `--reserved`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    led: Option<LedJson>,
+    /// Hex string of reserved bits, only with `--reserved`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reserved: Option<String>,
+}
+
+#[derive(Serialize)]
+struct LedJson {
+    leds: Vec<LedEntryJson>,
+    all_link_activity: bool,
+    blink_interval: CodeJson,
+    blink_duty_cycle: CodeJson,
+    /// `null` if the interval depends on link speed
+    blink_timing: Option<BlinkTimingJson>,
+    /// Hex string of raw register value, e.g. "0x000e0087"
+    raw: String,
+    raw_fields: RawFieldsJson,
+}
+
+#[derive(Serialize)]
+struct BlinkTimingJson {
+    period_ms: u32,
+    on_ms: u32,
+    off_ms: u32,
+}
+
+/// Fields of raw register value, see [`RawFields`]
+#[derive(Serialize)]
+struct RawFieldsJson {
+    led_select: u16,
+    high_active: u8,
+    all_link_activity: u8,
+    interval: u8,
+    duty: u8,
+    /// Hex string of reserved bits, e.g. "0x00000000"
+    reserved: String,
+}
+
+#[derive(Serialize)]
+struct LedEntryJson {
+    index: u8,
+    /// Link speeds in Mbps the LED lights on
+    link: Vec<u16>,
+    activity: bool,
+    /// Interpreted activity behavior, see [`crate::led::ActivityMode::name`]
+    activity_mode: &'static str,
+    high_active: bool,
+    /// LED select nibble of raw register value
+    led_select: u8,
+}
+
+/// Numeric code of a register field with its human readable meaning
+#[derive(Serialize)]
+struct CodeJson {
+    code: u8,
+    name: String,
+}
+
+impl<'a> DeviceJson<'a> {
+    fn new(info: &'a DeviceInfo, reserved_only: bool) -> Self {
+        let led_config = &info.led_config;
+        DeviceJson {
+            schema_version: SCHEMA_VERSION,
+            bus: info.bus,
+            address: info.addr,
+            vid: format!("{:04x}", info.vid),
+            pid: format!("{:04x}", info.pid),
+            manufacturer: &info.manufacturer,
+            product: &info.product,
+            serial: &info.serial,
+            id: &info.stable_id,
+            version: format!("{:?}", info.version),
+            chip: info.version.name(),
+            led: (!reserved_only).then(|| led_config.into()),
+            reserved: reserved_only.then(|| format!("0x{:08x}", led_config.raw_fields().reserved)),
+        }
+    }
+}
+
+impl From<&LedGlobalConfig> for LedJson {
+    fn from(config: &LedGlobalConfig) -> Self {
+        let fields = config.raw_fields();
+        LedJson {
+            leds: vec![
+                led_entry(&config.led_0, config.all_link_activity, &fields),
+                led_entry(&config.led_1, config.all_link_activity, &fields),
+                led_entry(&config.led_2, config.all_link_activity, &fields),
+            ],
+            all_link_activity: config.all_link_activity,
+            blink_interval: CodeJson {
+                code: config.blink_interval as u8,
+                name: config.blink_interval.to_string(),
+            },
+            blink_duty_cycle: CodeJson {
+                code: config.blink_duty_cycle as u8,
+                name: config.blink_duty_cycle.to_string(),
+            },
+            blink_timing: config
+                .blink_interval
+                .on_off_ms(config.blink_duty_cycle)
+                .map(|(on, off)| BlinkTimingJson {
+                    period_ms: on + off,
+                    on_ms: on,
+                    off_ms: off,
+                }),
+            raw: format!("0x{:08x}", config.to_raw()),
+            raw_fields: RawFieldsJson {
+                led_select: fields.led_select,
+                high_active: fields.high_active,
+                all_link_activity: fields.all_link_activity,
+                interval: fields.interval,
+                duty: fields.duty,
+                reserved: format!("0x{:08x}", fields.reserved),
+            },
+        }
+    }
+}
+
+fn led_entry<const I: u8>(
+    led: &LedConfig<I>,
+    all_link_activity: bool,
+    fields: &RawFields,
+) -> LedEntryJson {
+    let link = [(led.link10, 10), (led.link100, 100), (led.link1000, 1000)]
+        .into_iter()
+        .filter_map(|(enabled, speed)| enabled.then_some(speed))
+        .collect();
+    LedEntryJson {
+        index: I,
+        link,
+        activity: led.activity,
+        activity_mode: led.activity_mode(all_link_activity).name(),
+        high_active: led.high_active,
+        led_select: fields.led_select_of(I),
+    }
+}
+
+/// Writes devices as pretty printed JSON, a single object if there is exactly
+/// one device, otherwise an array. Only reserved bits of LED register are
+/// included if `reserved_only`.
+pub fn write_devices(
+    out: &mut dyn Write,
+    devices: &[DeviceInfo],
+    reserved_only: bool,
+) -> Result<()> {
+    let entries = devices
+        .iter()
+        .map(|info| DeviceJson::new(info, reserved_only))
+        .collect::<Vec<_>>();
+    let res = match entries.as_slice() {
+        [entry] => serde_json::to_writer_pretty(&mut *out, entry),
+        _ => serde_json::to_writer_pretty(&mut *out, &entries),
+    };
+    res.map_err(std::io::Error::from).context("writing JSON")?;
+    writeln!(out)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn led_json_fields() {
+        let led =
+            serde_json::to_value(LedJson::from(&LedGlobalConfig::from_raw(0x8004_0087))).unwrap();
+        assert_eq!(led["raw"], "0x80040087");
+        assert_eq!(
+            led["raw_fields"],
+            json!({
+                "led_select": 0x087,
+                "high_active": 0,
+                "all_link_activity": 0,
+                "interval": 1,
+                "duty": 0,
+                "reserved": "0x80000000",
+            })
+        );
+        assert_eq!(
+            led["blink_timing"],
+            json!({ "period_ms": 160, "on_ms": 20, "off_ms": 140 })
+        );
+        let selects: Vec<_> = (0..3)
+            .map(|i| led["leds"][i]["led_select"].clone())
+            .collect();
+        assert_eq!(selects, [7, 8, 0]);
+
+        let led = serde_json::to_value(LedJson::from(&LedGlobalConfig::from_raw(0xe0087))).unwrap();
+        assert!(led["blink_timing"].is_null());
+    }
+}
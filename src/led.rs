@@ -3,21 +3,48 @@
 // SPDX-License-Identifier: Apache-2.0
 use std::fmt;
 
-use rusb::UsbContext;
-
-use crate::device::{CtrlDevice, RegType};
+use crate::device::{CtrlDevice, RegType, UsbTransfer, Version};
 use crate::result::{Error, Result};
 
 const PLA_LED_SELECT: u16 = 0xdd90;
+const PLA_LED_FEATURE: u16 = 0xdd92;
+
+/// Whether offset is within LED register
+pub fn is_led_register(ty: RegType, offset: u16) -> bool {
+    ty == RegType::Pla && (PLA_LED_SELECT..PLA_LED_SELECT + 4).contains(&offset)
+}
 
 const LED_SEL_LINK_10: u32 = 1;
 const LED_SEL_LINK_100: u32 = 1 << 1;
 const LED_SEL_LINK_1000: u32 = 1 << 2;
 const LED_SEL_ACTIVITY: u32 = 1 << 3;
 
-const LED_VALUE_MASK: u32 = 0xf_ffff;
+const LED_HIGH_ACTIVE_SHIFT: u8 = 12;
+const LED_ALL_LINK_ACTIVITY: u32 = 1 << 15;
+const BLINK_DUTY_CYCLE_SHIFT: u8 = 16;
+const BLINK_INTERVAL_SHIFT: u8 = 18;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// Bits of LED register with known meaning, other bits are reserved
+pub const LED_VALUE_MASK: u32 = 0xf_ffff;
+
+/// Name of given bit of LED register, e.g. "LED0 ACTIVITY", bits outside of
+/// [`LED_VALUE_MASK`] are "RESERVED"
+pub fn bit_name(bit: u8) -> String {
+    const SELECT: [&str; 4] = ["LINK10", "LINK100", "LINK1000", "ACTIVITY"];
+    match bit {
+        0..=11 => format!("LED{} {}", bit / 4, SELECT[bit as usize % 4]),
+        12..=14 => format!("LED{} HIGH_ACTIVE", bit - LED_HIGH_ACTIVE_SHIFT),
+        15 => "ALL_LINK_ACTIVITY".to_string(),
+        16..=17 => format!("DUTY_CYCLE[{}]", bit - BLINK_DUTY_CYCLE_SHIFT),
+        18..=19 => format!("INTERVAL[{}]", bit - BLINK_INTERVAL_SHIFT),
+        _ => "RESERVED".to_string(),
+    }
+}
+
+const LED_MODE_SHIFT: u16 = 8;
+const LED_MODE_MASK: u16 = 0x0700;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct LedConfig<const I: u8> {
     pub link10: bool,
     pub link100: bool,
@@ -26,11 +53,118 @@ pub struct LedConfig<const I: u8> {
     pub high_active: bool,
 }
 
+/// Link speeds an LED lights on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LedLinks {
+    pub link10: bool,
+    pub link100: bool,
+    pub link1000: bool,
+}
+
 impl<const I: u8> LedConfig<I> {
+    pub fn links(&self) -> LedLinks {
+        LedLinks {
+            link10: self.link10,
+            link100: self.link100,
+            link1000: self.link1000,
+        }
+    }
+
+    pub fn set_links(&mut self, links: LedLinks) {
+        self.link10 = links.link10;
+        self.link100 = links.link100;
+        self.link1000 = links.link1000;
+    }
+
+    /// Interprets when the LED blinks, `all_link_activity` is the global
+    /// [`LedGlobalConfig::all_link_activity`] flag
+    pub fn activity_mode(&self, all_link_activity: bool) -> ActivityMode {
+        if !self.activity {
+            ActivityMode::None
+        } else if all_link_activity {
+            ActivityMode::AllLinksGlobal
+        } else if self.links() == LedLinks::default() {
+            ActivityMode::AllLinksNoSpeed
+        } else {
+            ActivityMode::SelectedLinks
+        }
+    }
+
+    /// Link speeds the LED lights on, in human readable form
+    pub fn link_str(&self) -> String {
+        let mut link = Vec::new();
+        if self.link10 {
+            link.push("10Mbps");
+        }
+        if self.link100 {
+            link.push("100Mbps");
+        }
+        if self.link1000 {
+            link.push("1000Mbps");
+        }
+        if link.is_empty() {
+            "Not triggered".to_string()
+        } else {
+            link.join(", ")
+        }
+    }
+
+    /// Polarity of the LED, in human readable form
+    pub fn light_str(&self) -> &'static str {
+        if self.high_active {
+            "Reversed"
+        } else {
+            "Not reversed"
+        }
+    }
+
+    fn fields(&self, all_link_activity: bool, res: &mut Vec<Field>) {
+        let led = Some(I);
+        res.push(Field {
+            led,
+            name: "Link",
+            value: self.link_str(),
+        });
+        res.push(Field {
+            led,
+            name: "Activity",
+            value: self.activity_mode(all_link_activity).to_string(),
+        });
+        res.push(Field {
+            led,
+            name: "Light",
+            value: self.light_str().to_string(),
+        });
+    }
+
+    /// Describes register bits of fields changed from `self` to `new`
+    fn explain_changes(&self, new: &Self, res: &mut Vec<String>) {
+        let fields = [
+            ("link10", LED_SEL_LINK_10, self.link10, new.link10),
+            ("link100", LED_SEL_LINK_100, self.link100, new.link100),
+            ("link1000", LED_SEL_LINK_1000, self.link1000, new.link1000),
+            ("activity", LED_SEL_ACTIVITY, self.activity, new.activity),
+        ];
+        for (name, sel, old, new) in fields {
+            if old != new {
+                let bit = sel.trailing_zeros() + I as u32 * 4;
+                res.push(format!("LED{} {} -> bit {}={}", I, name, bit, new as u8));
+            }
+        }
+        if self.high_active != new.high_active {
+            res.push(format!(
+                "LED{} high_active -> bit {}={}",
+                I,
+                LED_HIGH_ACTIVE_SHIFT + I,
+                new.high_active as u8
+            ));
+        }
+    }
+
     fn from_raw(value: u32) -> Self {
         assert!(I < 3);
         let led_select = value >> (I * 4);
-        let high_active = value & (1 << (12 + I));
+        let high_active = value & (1 << (LED_HIGH_ACTIVE_SHIFT + I));
 
         Self {
             link10: led_select & LED_SEL_LINK_10 != 0,
@@ -58,13 +192,63 @@ impl<const I: u8> LedConfig<I> {
         led_select <<= I * 4;
 
         if self.high_active {
-            led_select |= 1 << (12 + I);
+            led_select |= 1 << (LED_HIGH_ACTIVE_SHIFT + I);
         }
 
         led_select
     }
 }
 
+/// Field of LED configuration rendered in human readable form, shared by
+/// output formats so they agree on what fields there are
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    /// Index of the LED for per-LED fields, `None` for global fields
+    pub led: Option<u8>,
+    pub name: &'static str,
+    pub value: String,
+}
+
+/// Name of the raw register value field in [`LedGlobalConfig::fields`]
+pub const RAW_FIELD: &str = "Raw register value";
+
+/// Interpreted activity behavior of an LED
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActivityMode {
+    /// blinks on all speed of links as `all_link_activity` is set
+    AllLinksGlobal,
+    /// blinks on all speed of links as no link speed is selected for the LED
+    AllLinksNoSpeed,
+    /// blinks on selected link speeds only
+    SelectedLinks,
+    /// does not blink
+    None,
+}
+
+impl ActivityMode {
+    /// Machine readable name
+    pub fn name(self) -> &'static str {
+        use ActivityMode::*;
+        match self {
+            AllLinksGlobal => "all_links_global",
+            AllLinksNoSpeed => "all_links_no_speed",
+            SelectedLinks => "selected_links",
+            None => "none",
+        }
+    }
+}
+
+impl fmt::Display for ActivityMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ActivityMode::*;
+        f.write_str(match self {
+            AllLinksGlobal | AllLinksNoSpeed => "Blink on all speed of links",
+            SelectedLinks => "Blink on selected links",
+            None => "Not triggered",
+        })
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BlinkInterval {
     I240 = 0,
@@ -86,12 +270,31 @@ impl BlinkInterval {
         Ok(res)
     }
 
+    /// Blink period in milliseconds, `None` for link speed dependent interval
+    pub fn period_ms(self) -> Option<u32> {
+        use BlinkInterval::*;
+        match self {
+            I240 => Some(240),
+            I160 => Some(160),
+            I80 => Some(80),
+            ILink => None,
+        }
+    }
+
+    /// LED on and off milliseconds in a blink period with given duty cycle,
+    /// `None` for link speed dependent interval
+    pub fn on_off_ms(self, duty_cycle: BlinkDutyCycle) -> Option<(u32, u32)> {
+        let period = self.period_ms()?;
+        let on = period * duty_cycle.permille() / 1000;
+        Some((on, period - on))
+    }
+
     fn from_raw(value: u32) -> Self {
-        Self::from_num(((value >> 18) & 0b11) as _).unwrap()
+        Self::from_num(((value >> BLINK_INTERVAL_SHIFT) & 0b11) as _).unwrap()
     }
 
     fn to_raw(self) -> u32 {
-        (self as u32) << 18
+        (self as u32) << BLINK_INTERVAL_SHIFT
     }
 }
 
@@ -128,12 +331,23 @@ impl BlinkDutyCycle {
         Ok(res)
     }
 
+    /// Ratio of LED on time in a blink period, in permille
+    pub fn permille(self) -> u32 {
+        use BlinkDutyCycle::*;
+        match self {
+            R12_5 => 125,
+            R25 => 250,
+            R50 => 500,
+            R75 => 750,
+        }
+    }
+
     fn from_raw(value: u32) -> Self {
-        Self::from_num(((value >> 16) & 0b11) as _).unwrap()
+        Self::from_num(((value >> BLINK_DUTY_CYCLE_SHIFT) & 0b11) as _).unwrap()
     }
 
     fn to_raw(self) -> u32 {
-        (self as u32) << 16
+        (self as u32) << BLINK_DUTY_CYCLE_SHIFT
     }
 }
 
@@ -149,6 +363,38 @@ impl fmt::Display for BlinkDutyCycle {
     }
 }
 
+/// LED register value split into its fields, each shifted down to bit 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawFields {
+    /// LED select nibbles, LED 0 in bits `[3:0]`
+    pub led_select: u16,
+    /// High active bits, LED 0 in bit 0
+    pub high_active: u8,
+    pub all_link_activity: u8,
+    pub interval: u8,
+    pub duty: u8,
+    /// Bits outside of [`LED_VALUE_MASK`], not shifted
+    pub reserved: u32,
+}
+
+impl RawFields {
+    pub fn from_raw(value: u32) -> Self {
+        Self {
+            led_select: (value & 0xfff) as _,
+            high_active: ((value >> LED_HIGH_ACTIVE_SHIFT) & 0b111) as _,
+            all_link_activity: ((value & LED_ALL_LINK_ACTIVITY) != 0) as _,
+            interval: ((value >> BLINK_INTERVAL_SHIFT) & 0b11) as _,
+            duty: ((value >> BLINK_DUTY_CYCLE_SHIFT) & 0b11) as _,
+            reserved: value & !LED_VALUE_MASK,
+        }
+    }
+
+    /// LED select nibble of given LED
+    pub fn led_select_of(&self, led: u8) -> u8 {
+        ((self.led_select >> (led * 4)) & 0xf) as _
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct LedGlobalConfig {
     pub led_0: LedConfig<0>,
@@ -162,7 +408,7 @@ pub struct LedGlobalConfig {
 
 impl LedGlobalConfig {
     pub fn from_raw(value: u32) -> Self {
-        let all_link_activity = value & (1 << 15);
+        let all_link_activity = value & LED_ALL_LINK_ACTIVITY;
 
         Self {
             led_0: LedConfig::from_raw(value),
@@ -179,7 +425,11 @@ impl LedGlobalConfig {
         let led_0 = self.led_0.to_raw();
         let led_1 = self.led_1.to_raw();
         let led_2 = self.led_2.to_raw();
-        let all_link_activity = (self.all_link_activity as u32) << 15;
+        let all_link_activity = if self.all_link_activity {
+            LED_ALL_LINK_ACTIVITY
+        } else {
+            0
+        };
         let blink_interval = self.blink_interval.to_raw();
         let blink_duty_cycle = self.blink_duty_cycle.to_raw();
 
@@ -192,12 +442,280 @@ impl LedGlobalConfig {
             | (self.unknown & !LED_VALUE_MASK)
     }
 
-    pub fn read_from<T: UsbContext>(ctrl: &CtrlDevice<T>) -> Result<Self> {
+    pub fn raw_fields(&self) -> RawFields {
+        RawFields::from_raw(self.to_raw())
+    }
+
+    /// Opinionated default, LED 0 lights on link of all speeds and LED 1 blinks
+    /// on activity, raw value `0xe0087`.
+    pub fn opinionated_default() -> Self {
+        Self {
+            led_0: LedConfig {
+                link10: true,
+                link100: true,
+                link1000: true,
+                activity: false,
+                high_active: false,
+            },
+            led_1: LedConfig {
+                link10: false,
+                link100: false,
+                link1000: false,
+                activity: true,
+                high_active: false,
+            },
+            led_2: LedConfig {
+                link10: false,
+                link100: false,
+                link1000: false,
+                activity: false,
+                high_active: false,
+            },
+            all_link_activity: false,
+            blink_interval: BlinkInterval::ILink,
+            blink_duty_cycle: BlinkDutyCycle::R50,
+            unknown: 0,
+        }
+    }
+
+    /// Applies opinionated default tailored to capabilities of the chip,
+    /// reserved bits are kept.
+    pub fn apply_defaults_for(&mut self, version: Version) {
+        let caps = version.capabilities();
+        let default = Self::opinionated_default();
+
+        self.led_0 = default.led_0;
+        self.led_0.link1000 = caps.link1000;
+        self.led_1 = default.led_1;
+        self.led_2 = default.led_2;
+        self.all_link_activity = default.all_link_activity;
+        self.blink_interval = default.blink_interval;
+        self.blink_duty_cycle = default.blink_duty_cycle;
+    }
+
+    /// All fields in display order, per-LED fields first
+    pub fn fields(&self) -> Vec<Field> {
+        let mut res = Vec::new();
+        self.led_0.fields(self.all_link_activity, &mut res);
+        self.led_1.fields(self.all_link_activity, &mut res);
+        self.led_2.fields(self.all_link_activity, &mut res);
+        res.push(Field {
+            led: None,
+            name: "Blink interval",
+            value: self.blink_interval.to_string(),
+        });
+        res.push(Field {
+            led: None,
+            name: "Blink duty cycle(ratio)",
+            value: self.blink_duty_cycle.to_string(),
+        });
+        res.push(Field {
+            led: None,
+            name: "Blink timing",
+            value: match self.blink_interval.on_off_ms(self.blink_duty_cycle) {
+                Some((on, off)) => format!("{}ms period -> {}ms on / {}ms off", on + off, on, off),
+                None => "Depends on link speed".to_string(),
+            },
+        });
+        res.push(Field {
+            led: None,
+            name: RAW_FIELD,
+            value: format!("0x{:05x}", self.to_raw()),
+        });
+        res
+    }
+
+    /// Describes register bits of each field changed from `self` to `new`,
+    /// e.g. "LED0 link1000 -> bit 2=1"
+    pub fn explain_changes(&self, new: &Self) -> Vec<String> {
+        let mut res = Vec::new();
+        self.led_0.explain_changes(&new.led_0, &mut res);
+        self.led_1.explain_changes(&new.led_1, &mut res);
+        self.led_2.explain_changes(&new.led_2, &mut res);
+        if self.all_link_activity != new.all_link_activity {
+            res.push(format!(
+                "all_link_activity -> bit {}={}",
+                LED_ALL_LINK_ACTIVITY.trailing_zeros(),
+                new.all_link_activity as u8
+            ));
+        }
+        if self.blink_duty_cycle != new.blink_duty_cycle {
+            res.push(format!(
+                "duty cycle {} -> bits [{}:{}]=0b{:02b}",
+                new.blink_duty_cycle,
+                BLINK_DUTY_CYCLE_SHIFT + 1,
+                BLINK_DUTY_CYCLE_SHIFT,
+                new.blink_duty_cycle as u8
+            ));
+        }
+        if self.blink_interval != new.blink_interval {
+            res.push(format!(
+                "interval {} -> bits [{}:{}]=0b{:02b}",
+                new.blink_interval,
+                BLINK_INTERVAL_SHIFT + 1,
+                BLINK_INTERVAL_SHIFT,
+                new.blink_interval as u8
+            ));
+        }
+        let unknown = new.unknown & !LED_VALUE_MASK;
+        if self.unknown & !LED_VALUE_MASK != unknown {
+            res.push(format!("reserved bits -> 0x{:08x}", unknown));
+        }
+        res
+    }
+
+    /// Whether known LED fields equal [`Self::opinionated_default`], ignoring
+    /// reserved bits
+    pub fn is_default(&self) -> bool {
+        self.fields_eq(&Self::opinionated_default())
+    }
+
+    /// Whether known LED fields are equal, ignoring reserved bits
+    pub fn fields_eq(&self, other: &Self) -> bool {
+        self.to_raw() & LED_VALUE_MASK == other.to_raw() & LED_VALUE_MASK
+    }
+
+    /// Reads LED register, rejecting all-ones value with
+    /// [`Error::SuspectRead`] which is likely read from a disconnected device
+    /// or through a wrong interface
+    pub fn read_from<H: UsbTransfer>(ctrl: &CtrlDevice<H>) -> Result<Self> {
         let value = ctrl.read_dword(RegType::Pla, PLA_LED_SELECT)?;
+        Self::from_read(value)
+    }
+
+    /// Same as [`Self::from_raw`], rejecting suspect values read from device,
+    /// see [`Self::read_from`]
+    fn from_read(value: u32) -> Result<Self> {
+        if value == u32::MAX {
+            return Err(Error::SuspectRead(value));
+        }
         Ok(Self::from_raw(value))
     }
 
-    pub fn write_to<T: UsbContext>(&self, ctrl: &CtrlDevice<T>) -> Result<()> {
+    pub fn read_from_unchecked<H: UsbTransfer>(ctrl: &CtrlDevice<H>) -> Result<Self> {
+        let value = ctrl.read_dword(RegType::Pla, PLA_LED_SELECT)?;
+        Ok(Self::from_raw(value))
+    }
+
+    pub fn write_to<H: UsbTransfer>(&self, ctrl: &CtrlDevice<H>) -> Result<()> {
         ctrl.write_dword(RegType::Pla, PLA_LED_SELECT, self.to_raw())
     }
+
+    /// Writes as two word writes, the lower word (LED selections and
+    /// polarity) first, then the upper word (blink settings), for chips
+    /// glitching LEDs on a single dword write
+    pub fn write_split_to<H: UsbTransfer>(&self, ctrl: &CtrlDevice<H>) -> Result<()> {
+        let value = self.to_raw();
+        ctrl.write_word(RegType::Pla, PLA_LED_SELECT, value as u16)?;
+        ctrl.write_word(RegType::Pla, PLA_LED_FEATURE, (value >> 16) as u16)
+    }
+}
+
+/// LED feature register, the upper word of LED register at `0xdd92`.
+///
+/// Bits `[3:0]` are the blink settings covered by [`LedGlobalConfig`], bits
+/// `[10:8]` select the LED mode. The meaning of LED modes is undocumented, the
+/// r8152 driver clears them on initialization of RTL8153 and later chips.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LedFeatureConfig {
+    pub mode: u8,
+    pub unknown: u16,
+}
+
+impl LedFeatureConfig {
+    pub fn from_raw(value: u16) -> Self {
+        Self {
+            mode: ((value & LED_MODE_MASK) >> LED_MODE_SHIFT) as _,
+            unknown: value & !LED_MODE_MASK,
+        }
+    }
+
+    pub fn to_raw(&self) -> u16 {
+        (((self.mode as u16) << LED_MODE_SHIFT) & LED_MODE_MASK) | (self.unknown & !LED_MODE_MASK)
+    }
+
+    pub fn read_from<H: UsbTransfer>(ctrl: &CtrlDevice<H>) -> Result<Self> {
+        if !ctrl.version()?.capabilities().led_feature {
+            return Err(Error::Unsupported);
+        }
+        let value = ctrl.read_word(RegType::Pla, PLA_LED_FEATURE)?;
+        Ok(Self::from_raw(value))
+    }
+
+    pub fn write_to<H: UsbTransfer>(&self, ctrl: &CtrlDevice<H>) -> Result<()> {
+        if !ctrl.version()?.capabilities().led_feature {
+            return Err(Error::Unsupported);
+        }
+        ctrl.write_word(RegType::Pla, PLA_LED_FEATURE, self.to_raw())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_default_matching() {
+        assert!(LedGlobalConfig::opinionated_default().is_default());
+        assert!(LedGlobalConfig::from_raw(0xe0087).is_default());
+        // reserved bits are ignored
+        assert!(LedGlobalConfig::from_raw(0xfff0_0000 | 0xe0087).is_default());
+    }
+
+    #[test]
+    fn is_default_non_matching() {
+        for raw in [0, 0xe008f, 0xe1087, 0x60087, 0xf_ffff] {
+            assert!(!LedGlobalConfig::from_raw(raw).is_default(), "{:#x}", raw);
+        }
+    }
+
+    #[test]
+    fn all_ones_read_is_suspect() {
+        assert!(matches!(
+            LedGlobalConfig::from_read(u32::MAX),
+            Err(Error::SuspectRead(u32::MAX))
+        ));
+        for raw in [0, 0xe0087, 0xf_ffff, 0xffff_fffe] {
+            assert_eq!(LedGlobalConfig::from_read(raw).unwrap().to_raw(), raw);
+        }
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        for reserved in [0, 0x0010_0000, 0x0700_0000, 0x8000_0000, 0xfff0_0000] {
+            for value in 0..=LED_VALUE_MASK {
+                let raw = reserved | value;
+                assert_eq!(LedGlobalConfig::from_raw(raw).to_raw(), raw, "{:#x}", raw);
+            }
+        }
+    }
+    #[test]
+    fn raw_fields_split() {
+        let fields = LedGlobalConfig::from_raw(0xe0087).raw_fields();
+        assert_eq!(
+            fields,
+            RawFields {
+                led_select: 0x087,
+                high_active: 0,
+                all_link_activity: 0,
+                interval: 3,
+                duty: 2,
+                reserved: 0,
+            }
+        );
+        assert_eq!(
+            (0..3)
+                .map(|led| fields.led_select_of(led))
+                .collect::<Vec<_>>(),
+            [0x7, 0x8, 0x0]
+        );
+
+        let fields = RawFields::from_raw(0x8005_d421);
+        assert_eq!(fields.led_select, 0x421);
+        assert_eq!(fields.high_active, 0b101);
+        assert_eq!(fields.all_link_activity, 1);
+        assert_eq!(fields.interval, 1);
+        assert_eq!(fields.duty, 1);
+        assert_eq!(fields.reserved, 0x8000_0000);
+    }
 }
@@ -0,0 +1,9 @@
+// SPDX-FileCopyrightText: 2024 Huang-Huang Bao
+// SPDX-License-Identifier: MIT
+// SPDX-License-Identifier: Apache-2.0
+//! Control LEDs of Realtek RTL8152/8153 series USB NICs, see [`scan::scan`]
+//! for enumerating devices with their LED configuration.
+pub mod device;
+pub mod led;
+pub mod result;
+pub mod scan;
@@ -1,61 +1,165 @@
 // SPDX-FileCopyrightText: 2024 Huang-Huang Bao
 // SPDX-License-Identifier: MIT
 // SPDX-License-Identifier: Apache-2.0
-mod device;
-mod led;
-mod result;
 
+/// Defines a subcommand with device selector options prepended to its own
+/// options, and its `filter()` building [`Filter`] of them
+macro_rules! with_selectors {
+    (
+        $(#[$($attr:tt)*])*
+        $vis:vis struct $name:ident {
+            $($fields:tt)*
+        }
+    ) => {
+        $(#[$($attr)*])*
+        $vis struct $name {
+            /// bus_num:dev_num of USB device to select, separate multiple
+            /// devices with comma ",", e.g. "1:5,1:7"
+            #[argh(option)]
+            device: Option<$crate::ArgDevices>,
+
+            /// vender_id:product_id of USB device to select
+            #[argh(option)]
+            product: Option<$crate::ProductId>,
+
+            /// case-insensitive substring of manufacturer or product name of USB
+            /// device to select, slower than matching by product as it opens
+            /// every candidate
+            #[argh(option)]
+            name: Option<String>,
+
+            /// USB port path glob of device, in form of "bus-port.port" as in sysfs,
+            /// e.g. "1-1.*" for all ports of hub on port 1 of bus 1, "*" matches any
+            /// characters and "?" matches a single character
+            #[argh(option)]
+            usb_path: Option<String>,
+
+            /// bus_num:dev_num of USB device to exclude, can be repeated, excludes
+            /// override other selectors
+            #[argh(option)]
+            exclude: Vec<$crate::DeviceAddr>,
+
+            /// vender_id:product_id of USB devices to exclude, can be repeated
+            #[argh(option)]
+            exclude_product: Vec<$crate::ProductId>,
+
+            /// how to combine `--device`, `--product`, `--name` and `--usb-path`
+            /// supplied, "all" to select devices matching all of them, or "any" to
+            /// select devices matching any of them, defaults to "all"
+            #[argh(option, long = "match")]
+            match_mode: Option<$crate::MatchMode>,
+
+            /// only keep the first matched device on each USB bus, in order of
+            /// device number (lowest first)
+            #[argh(switch)]
+            once_per_bus: bool,
+
+            /// abort on devices failed to enumerate instead of skipping them
+            #[argh(switch)]
+            strict: bool,
+
+            $($fields)*
+        }
+
+        impl $name {
+            fn filter(&self) -> $crate::Filter {
+                $crate::Filter {
+                    devices: self.device.clone().map(|$crate::ArgDevices(devices)| devices),
+                    product: self.product,
+                    name: self.name.clone(),
+                    usb_path: self.usb_path.clone(),
+                    exclude: self.exclude.clone(),
+                    exclude_product: self.exclude_product.clone(),
+                    match_mode: self.match_mode.unwrap_or($crate::MatchMode::All),
+                    strict: self.strict,
+                    once_per_bus: self.once_per_bus,
+                }
+            }
+        }
+    };
+}
+
+mod completions;
+#[cfg(feature = "toml")]
+mod config;
+#[cfg(feature = "tui")]
+mod dashboard;
+#[cfg(feature = "json")]
+mod json;
+mod preset;
+mod profile;
+
+use std::io::{self, Write};
 use std::num::ParseIntError;
+use std::ops::Deref;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use argh::FromArgs;
+use rtl8152_led_ctrl::{device, led, result, scan};
 
-use device::{CtrlDevice, RegType};
-use result::{Error, Result};
-
-const VID_REALTEK: u16 = 0x0bda;
-const VID_MICROSOFT: u16 = 0x045e;
-const VID_SAMSUNG: u16 = 0x0419;
-const VID_LENOVO: u16 = 0x17ef;
-const VID_LINKSYS: u16 = 0x13b1;
-const VID_NVIDIA: u16 = 0x0955;
-const VID_TPLINK: u16 = 0x2357;
-const VID_DLINK: u16 = 0x2001;
-const VID_ASUS: u16 = 0x0b05;
-
-const RTL8152_DEVICE_VID_PIDS: &[(u16, u16)] = &[
-    (VID_REALTEK, 0x8050),
-    (VID_REALTEK, 0x8053),
-    (VID_REALTEK, 0x8152),
-    (VID_REALTEK, 0x8153),
-    (VID_REALTEK, 0x8155),
-    (VID_REALTEK, 0x8156),
-    (VID_MICROSOFT, 0x07ab),
-    (VID_MICROSOFT, 0x07c6),
-    (VID_MICROSOFT, 0x0927),
-    (VID_MICROSOFT, 0x0c5e),
-    (VID_SAMSUNG, 0xa101),
-    (VID_LENOVO, 0x304f),
-    (VID_LENOVO, 0x3054),
-    (VID_LENOVO, 0x3062),
-    (VID_LENOVO, 0x3069),
-    (VID_LENOVO, 0x3082),
-    (VID_LENOVO, 0x7205),
-    (VID_LENOVO, 0x720c),
-    (VID_LENOVO, 0x7214),
-    (VID_LENOVO, 0x721e),
-    (VID_LENOVO, 0xa387),
-    (VID_LINKSYS, 0x0041),
-    (VID_NVIDIA, 0x09ff),
-    (VID_TPLINK, 0x0601),
-    (VID_DLINK, 0xb301),
-    (VID_ASUS, 0x1976),
-];
+use device::{CtrlDevice, RegType, Version};
+use result::{Error, Result, ResultExt};
+use scan::{DeviceAddr, Filter, MatchMode, Options, ProductId};
 
 #[derive(FromArgs, PartialEq, Debug)]
 /// Realtek RTL8152/8153 LED Control
 #[argh(note = "Repo: https://github.com/EHfive/rtl8152-led-ctrl\nby @EHfive")]
 struct TopArgs {
+    /// claim USB interface of given index before accessing registers,
+    /// only needed for composite devices that accept vendor control requests
+    /// on an interface other than the one bound to kernel driver
+    #[argh(option)]
+    interface: Option<u8>,
+
+    /// only treat devices having interface of given classes as NICs, separate
+    /// hexadecimal class codes with comma ",", or "any" to disable the check,
+    /// defaults to "02,0a,ff" (CDC communications, CDC data, vendor specific)
+    #[argh(option)]
+    require_class: Option<ArgClasses>,
+
+    /// print diagnostic messages to stderr, repeat to also print statistics
+    /// of control transfers
+    #[argh(switch, short = 'v')]
+    verbose: u8,
+
+    /// treat devices as given chip version instead of probing it, e.g. "V9",
+    /// see `versions` for known versions
+    #[argh(option)]
+    assume_version: Option<Version>,
+
+    /// do not reject devices reporting unknown chip version, e.g. to dump
+    /// registers of unsupported chips
+    #[argh(switch)]
+    no_version_check: bool,
+
+    /// milliseconds to wait after opening a device before accessing its
+    /// registers, a last resort for flaky devices returning bad data right
+    /// after being opened, defaults to 0
+    #[argh(option)]
+    delay_after_open: Option<u64>,
+
+    /// timeout of each USB control transfer in milliseconds, lower it to
+    /// skip wedged devices sooner, defaults to 5000
+    #[argh(option)]
+    timeout_ms: Option<u64>,
+
+    /// detach kernel drivers (e.g. r8152, cdc_ether) from interfaces of
+    /// devices, or only from `--interface` if given, while accessing
+    /// registers, and reattach them afterwards, for systems where the bound
+    /// driver makes control transfers fail with access or busy errors
+    #[argh(switch)]
+    detach: bool,
+
+    /// times to retry a USB control transfer failed with transient errors,
+    /// i.e. timeout, pipe and I/O errors, with increasing backoff, defaults
+    /// to 2
+    #[argh(option)]
+    retries: Option<u32>,
+
     #[argh(subcommand)]
     cmd: CmdEnum,
 }
@@ -66,135 +170,524 @@ enum CmdEnum {
     Show(CmdShow),
     Set(CmdSet),
     Reg(CmdReg),
+    UpdateLed(CmdUpdateLed),
+    SystemdUnit(CmdSystemdUnit),
+    GenUdev(CmdGenUdev),
+    Completions(CmdCompletions),
+    Selftest(CmdSelftest),
+    Identify(CmdIdentify),
+    Watch(CmdWatch),
+    Dump(CmdDump),
+    Diff(CmdDiff),
+    Versions(CmdVersions),
+    #[cfg(feature = "tui")]
+    Dashboard(dashboard::CmdDashboard),
+    #[cfg(feature = "toml")]
+    Apply(config::CmdApply),
 }
 
-#[derive(FromArgs, PartialEq, Debug)]
-#[argh(subcommand, name = "show")]
-/// Show devices and LED configuration
-struct CmdShow {
-    /// bus_num:dev_num of USB device to show
-    #[argh(option)]
-    device: Option<ArgDevice>,
+with_selectors! {
+    #[derive(FromArgs, PartialEq, Debug)]
+    #[argh(subcommand, name = "show")]
+    /// Show devices and LED configuration
+    struct CmdShow {
+        /// also show USB interface descriptors of devices
+        #[argh(switch)]
+        verbose: bool,
+
+        /// dump USB device, configuration and interface descriptors of devices
+        #[argh(switch)]
+        dump_descriptors: bool,
+
+        /// report which preset the LED configuration matches
+        #[argh(switch)]
+        match_preset: bool,
+
+        /// file of user defined presets, each line in form of `name = raw_value`
+        #[argh(option)]
+        presets: Option<PathBuf>,
+
+        /// list matched devices from USB descriptors only, without opening
+        /// devices or reading LED configuration
+        #[argh(switch)]
+        no_open: bool,
+
+        /// also list devices from known vendors whose product ID is not in the
+        /// supported table, without opening them
+        #[argh(switch)]
+        list_unsupported: bool,
+
+        /// also write the report to given file, with device capabilities added,
+        /// for attaching to bug reports
+        #[argh(option)]
+        dump: Option<PathBuf>,
+
+        /// only show reserved bits of LED register, i.e. bits outside of known
+        /// LED fields, for comparing them across chip versions
+        #[argh(switch)]
+        reserved: bool,
+
+        /// group devices of identical product, chip version and LED register
+        /// value, showing one block per group
+        #[argh(switch)]
+        group: bool,
+
+        /// print devices in a canonical form suitable for tracking changes with
+        /// version control, see README for included fields
+        #[argh(switch)]
+        normalize_output: bool,
+
+        /// accept suspect LED register value of all ones
+        #[argh(switch)]
+        force: bool,
+
+        /// format of raw register value, "hex", "dec" or "bin", defaults to "hex"
+        #[argh(option)]
+        raw_format: Option<ArgRawFormat>,
+
+        /// print devices as JSON, an object for a single device or an array
+        /// otherwise, requires the `json` feature
+        #[argh(switch)]
+        json: bool,
+
+        /// write LED configuration of the device to given TOML file for `apply`,
+        /// requires the `toml` feature
+        #[argh(option)]
+        config_out: Option<PathBuf>,
+    }
+}
 
-    /// vender_id:product_id of USB device to show
-    #[argh(option)]
-    product: Option<ArgProduct>,
+with_selectors! {
+    #[derive(FromArgs, PartialEq, Debug)]
+    #[argh(subcommand, name = "set")]
+    /// Set LED configuration
+    struct CmdSet {
+        /// by default we apply opinionated default value for unspecified options,
+        /// set `--no-default` to disable this behavior
+        #[argh(switch)]
+        no_default: bool,
+
+        /// base configuration other options apply to, "current" for current
+        /// configuration of device (same as `--no-default`), "default" for
+        /// opinionated default (the default unless `--no-default`), or
+        /// "raw:<value>" for given raw register value
+        #[argh(option)]
+        source: Option<ArgSource>,
+
+        /// LED 0 LINK, lit LED when link for speed 10(Mbps), 100(Mbps) or 1000(Mbps) is up,
+        /// separate speeds with comma ",", e.g. "10,100,1000",
+        /// pass 0 or empty string to deactivate
+        #[argh(option)]
+        led0_link: Option<ArgLink>,
+        /// LED 1 LINK, similar to `--led0-link`
+        #[argh(option)]
+        led1_link: Option<ArgLink>,
+        /// LED 2 LINK, similar to `--led0-link`
+        #[argh(option)]
+        led2_link: Option<ArgLink>,
+
+        /// LED 0 ACT, blink LED on link activity, true or false,
+        /// if the LINK for this LED is not set to any speed,
+        /// it will blink on all speed of links
+        #[argh(option)]
+        led0_act: Option<bool>,
+        /// LED 1 ACT, similar to `--led0-act`
+        #[argh(option)]
+        led1_act: Option<bool>,
+        /// LED 2 ACT, similar to `--led0-act`
+        #[argh(option)]
+        led2_act: Option<bool>,
+
+        /// LED 0 reverse, reverse LED polarity to high active, true or false,
+        /// overrides `--reverse-all` for this LED
+        #[argh(option)]
+        led0_reverse: Option<bool>,
+        /// LED 1 reverse, similar to `--led0-reverse`
+        #[argh(option)]
+        led1_reverse: Option<bool>,
+        /// LED 2 reverse, similar to `--led0-reverse`
+        #[argh(option)]
+        led2_reverse: Option<bool>,
+        /// reverse polarity of all LEDs to high active, true or false,
+        /// for LEDs wired with opposite polarity
+        #[argh(option)]
+        reverse_all: Option<bool>,
+
+        /// blink on all speed of links if ACT is enabled, applies to all LEDs, true or false
+        #[argh(option)]
+        act_all: Option<bool>,
+
+        /// blink interval, 0: 240ms, 1: 160ms, 2: 80ms, 3: link speed dependent
+        #[argh(option)]
+        interval: Option<u8>,
+
+        /// blink duty cycle, 0: 12.5%, 1: 25%, 2: 50%, 3: 75%
+        #[argh(option)]
+        duty_cycle: Option<u8>,
+
+        /// set raw LED register value as is, mutually exclusive with options of
+        /// LED fields, see `--source` for applying them on a raw value
+        #[argh(option)]
+        raw: Option<ArgU32>,
+
+        /// drop bits of `--raw` value outside of known LED fields instead of
+        /// writing them to reserved bits
+        #[argh(switch)]
+        clamp_reserved: bool,
+
+        /// keep reserved bits (outside of 0xfffff) of current LED register value
+        /// of the device, only writing bits of known LED fields, e.g. for `--raw`
+        /// or `--source raw:<value>` captured from another chip version
+        #[argh(switch)]
+        preserve_reserved: bool,
+
+        /// apply named preset, other options override fields of the preset,
+        /// see README for built-in presets
+        #[argh(option)]
+        preset: Option<String>,
+
+        /// file of user defined presets, each line in form of `name = raw_value`
+        #[argh(option)]
+        presets: Option<PathBuf>,
+
+        /// directory of per-device config files, looks up `<stable-id>.conf` then
+        /// `default.conf`, each holding a raw value or preset name, falls back to
+        /// opinionated default if none exists, ignored with `--raw` or `--preset`
+        #[argh(option)]
+        config_dir: Option<PathBuf>,
+
+        /// LED mode in LED feature register, 0 to 7, RTL8153 and later only,
+        /// the meaning of modes is undocumented
+        #[argh(option)]
+        led_mode: Option<ArgLedMode>,
+
+        /// only apply if current raw LED register value equals given value,
+        /// otherwise abort with exit code 3
+        #[argh(option)]
+        apply_if_changed_from: Option<ArgU32>,
+
+        /// after writing, read back LED register and abort with exit code 4 if
+        /// it does not equal given raw value, unlike comparing against the value
+        /// computed from other options, this asserts an externally known value
+        #[argh(option)]
+        confirm_value: Option<ArgU32>,
+
+        /// read back LED register after writing and retry the write up to given
+        /// times until LED fields match, for chips needing a few writes to latch
+        #[argh(option)]
+        write_verify_retries: Option<u32>,
+
+        /// write LED register as two word writes, lower word first, then upper
+        /// word, a workaround for adapters flickering LEDs on a single dword write
+        #[argh(switch)]
+        split_write: bool,
+
+        /// dry run, print result LED configuration only
+        #[argh(switch)]
+        dry: bool,
+
+        /// with `--dry`, also list register bits set by each changed field
+        #[argh(switch)]
+        explain: bool,
+
+        /// accept suspect LED register value of all ones
+        #[argh(switch)]
+        force: bool,
+
+        /// apply to all matched devices instead of the first one, continuing on
+        /// failures, exits with 1 if any device failed
+        #[argh(switch)]
+        all: bool,
+
+        /// with `--all`, milliseconds to wait between devices, for hubs failing
+        /// on back-to-back transfers to many devices, defaults to 0
+        #[argh(option)]
+        delay_ms: Option<u64>,
+
+        /// after setting, poll LED register of the device and print changes
+        /// like `watch` until Ctrl-C
+        #[argh(switch)]
+        apply_and_watch: bool,
+
+        /// with `--apply-and-watch`, polling interval in milliseconds, defaults
+        /// to 1000
+        #[argh(option)]
+        interval_ms: Option<u64>,
+
+        /// load device selector and options from named profile, options given
+        /// on command line override those of the profile
+        #[argh(option)]
+        profile: Option<String>,
+
+        /// file of profiles, each line in form of `name = arguments`, defaults to
+        /// "$XDG_CONFIG_HOME/rtl8152-led-ctrl/profiles"
+        #[argh(option)]
+        profiles: Option<PathBuf>,
+    }
 }
 
-#[derive(FromArgs, PartialEq, Debug)]
-#[argh(subcommand, name = "set")]
-/// Set LED configuration
-struct CmdSet {
-    /// bus_num:dev_num of USB device to control
-    #[argh(option)]
-    device: Option<ArgDevice>,
+with_selectors! {
+    #[derive(FromArgs, PartialEq, Debug)]
+    #[argh(subcommand, name = "update-led")]
+    /// Update configuration of a single LED, keeping other LEDs and blink settings
+    struct CmdUpdateLed {
+        /// index of LED to update, 0, 1 or 2
+        #[argh(option)]
+        led: ArgLedIndex,
+
+        /// LINK of the LED, similar to `set --led0-link`
+        #[argh(option)]
+        link: Option<ArgLink>,
+
+        /// ACT of the LED, similar to `set --led0-act`
+        #[argh(option)]
+        act: Option<bool>,
+
+        /// reverse polarity of the LED to high active, true or false
+        #[argh(option)]
+        reverse: Option<bool>,
+
+        /// dry run, print changes of the LED only
+        #[argh(switch)]
+        dry: bool,
+
+        /// accept suspect LED register value of all ones
+        #[argh(switch)]
+        force: bool,
+    }
+}
 
-    /// vender_id:product_id of USB device to control
-    #[argh(option)]
-    product: Option<ArgProduct>,
+with_selectors! {
+    #[derive(FromArgs, PartialEq, Debug)]
+    #[argh(subcommand, name = "selftest")]
+    /// Cycle LEDs through a sequence of states to verify wiring and polarity
+    struct CmdSelftest {
+        /// pause between steps in milliseconds, defaults to 1000
+        #[argh(option)]
+        step_ms: Option<u64>,
+
+        /// light LEDs one at a time in order of index repeatedly until Ctrl-C,
+        /// for mapping LED indices to physical LEDs
+        #[argh(switch)]
+        led_test_pattern: bool,
+
+        /// accept suspect LED register value of all ones
+        #[argh(switch)]
+        force: bool,
+    }
+}
 
-    /// by default we apply opinionated default value for unspecified options,
-    /// set `--no-default` to disable this behavior
-    #[argh(switch)]
-    no_default: bool,
+with_selectors! {
+    #[derive(FromArgs, PartialEq, Debug)]
+    #[argh(subcommand, name = "dump")]
+    /// Print a register region as hex dump
+    #[argh(example = "{command_name} --type pla --offset 0xdd00 --length 0x40")]
+    struct CmdDump {
+        /// register type, "pla" or "usb", defaults to "pla"
+        #[argh(option, long = "type")]
+        ty: Option<RegType>,
+
+        /// start register offset, dword aligned
+        #[argh(option)]
+        offset: ArgU16,
+
+        /// length of region in bytes, dword aligned
+        #[argh(option)]
+        length: ArgU16,
+    }
+}
 
-    /// LED 0 LINK, lit LED when link for speed 10(Mbps), 100(Mbps) or 1000(Mbps) is up,
-    /// separate speeds with comma ",", e.g. "10,100,1000",
-    /// pass 0 or empty string to deactivate
-    #[argh(option)]
-    led0_link: Option<ArgLink>,
-    /// LED 1 LINK, similar to `--led0-link`
-    #[argh(option)]
-    led1_link: Option<ArgLink>,
-    /// LED 2 LINK, similar to `--led0-link`
-    #[argh(option)]
-    led2_link: Option<ArgLink>,
+with_selectors! {
+    #[derive(FromArgs, PartialEq, Debug)]
+    #[argh(subcommand, name = "diff")]
+    /// Compare two LED register values field by field and bit by bit
+    #[argh(example = "{command_name} --raw-a 0xe0087 --raw-b 0xe008f")]
+    #[argh(note = "Without `--raw-a`, value A is read from the selected device.")]
+    struct CmdDiff {
+        /// raw LED register value A, conflicts with device selectors
+        #[argh(option)]
+        raw_a: Option<ArgU32>,
+
+        /// raw LED register value B
+        #[argh(option)]
+        raw_b: ArgU32,
+
+        /// accept suspect LED register value of all ones
+        #[argh(switch)]
+        force: bool,
+    }
+}
 
-    /// LED 0 ACT, blink LED on link activity, true or false,
-    /// if the LINK for this LED is not set to any speed,
-    /// it will blink on all speed of links
-    #[argh(option)]
-    led0_act: Option<bool>,
-    /// LED 1 ACT, similar to `--led0-act`
-    #[argh(option)]
-    led1_act: Option<bool>,
-    /// LED 2 ACT, similar to `--led0-act`
-    #[argh(option)]
-    led2_act: Option<bool>,
+with_selectors! {
+    #[derive(FromArgs, PartialEq, Debug)]
+    #[argh(subcommand, name = "identify")]
+    /// Flash all LEDs of a device to locate its physical port, then restore
+    /// LED configuration
+    struct CmdIdentify {
+        /// seconds to flash LEDs, defaults to 5
+        #[argh(option)]
+        seconds: Option<u64>,
+
+        /// accept suspect LED register value of all ones
+        #[argh(switch)]
+        force: bool,
+    }
+}
 
-    /// LED 0 reverse, set LED to high active, true or false
-    #[argh(option)]
-    led0_reverse: Option<bool>,
-    /// LED 1 reverse, similar to `--led0-reverse`
-    #[argh(option)]
-    led1_reverse: Option<bool>,
-    /// LED 2 reverse, similar to `--led0-reverse`
+with_selectors! {
+    #[derive(FromArgs, PartialEq, Debug)]
+    #[argh(subcommand, name = "watch")]
+    /// Poll LED register and print changes until Ctrl-C
+    struct CmdWatch {
+        /// polling interval in milliseconds, defaults to 1000
+        #[argh(option)]
+        interval_ms: Option<u64>,
+
+        /// stop after given number of changes
+        #[argh(option)]
+        count: Option<u32>,
+
+        /// same as `--count`
+        #[argh(option)]
+        max_changes: Option<u32>,
+
+        /// stop after given seconds
+        #[argh(option)]
+        duration: Option<u64>,
+
+        /// accept suspect LED register value of all ones
+        #[argh(switch)]
+        force: bool,
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "systemd-unit")]
+/// Print systemd service unit running `set` on boot
+#[argh(example = "{command_name} --product 0bda:8153 -- --led0-link 10,100 --led1-act true")]
+#[argh(note = "Arguments after `--` are passed to `set` as is.")]
+struct CmdSystemdUnit {
+    /// bus_num:dev_num of USB device to control, note that device numbers
+    /// are not stable across reboots
     #[argh(option)]
-    led2_reverse: Option<bool>,
+    device: Option<ArgDevices>,
 
-    /// blink on all speed of links if ACT is enabled, applies to all LEDs, true or false
+    /// vender_id:product_id of USB device to control
     #[argh(option)]
-    act_all: Option<bool>,
+    product: Option<ProductId>,
 
-    /// blink interval, 0: 240ms, 1: 160ms, 2: 80ms, 3: link speed dependent
+    /// case-insensitive substring of manufacturer or product name of USB device
+    /// to control
     #[argh(option)]
-    interval: Option<u8>,
+    name: Option<String>,
 
-    /// blink duty cycle, 0: 12.5%, 1: 25%, 2: 50%, 3: 75%
+    /// USB port path glob of device to control, e.g. "1-1.*", stable across
+    /// reboots as long as the device stays plugged in the same port
     #[argh(option)]
-    duty_cycle: Option<u8>,
+    usb_path: Option<String>,
 
-    /// set raw LED register value
+    /// path to this executable in unit, defaults to path of current executable
     #[argh(option)]
-    raw: Option<ArgU32>,
+    exec: Option<String>,
 
-    /// dry run, print result LED configuration only
-    #[argh(switch)]
-    dry: bool,
+    /// arguments of `set`
+    #[argh(positional)]
+    args: Vec<String>,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
-#[argh(subcommand, name = "reg")]
-/// Read/write register directly
-struct CmdReg {
-    /// bus_num:dev_num of USB device to control
-    #[argh(option)]
-    device: Option<ArgDevice>,
-
-    /// vender_id:product_id of USB device to control
-    #[argh(option)]
-    product: Option<ArgProduct>,
-
-    /// register type, "pla" or "usb", defaults to "pla"
-    #[argh(option, long = "type")]
-    ty: Option<RegType>,
-
-    /// register offset, e.g. 0xdd90 for LED configuration
-    #[argh(option)]
-    offset: ArgU16,
-
-    /// register width, 8, 16 or 32, defaults to 32
+#[argh(subcommand, name = "gen-udev")]
+/// Print udev rules granting non-root users access to supported devices
+#[argh(example = "{command_name} --group plugdev > /etc/udev/rules.d/70-rtl8152-led-ctrl.rules")]
+struct CmdGenUdev {
+    /// group owning device nodes, members of which can access devices
     #[argh(option)]
-    width: Option<ArgWidth>,
+    group: Option<String>,
 
-    /// write value to register, e.g. 0xe0087
+    /// tag added to device nodes, can be repeated, e.g. "uaccess" to grant
+    /// access to users logged in locally via systemd-logind
     #[argh(option)]
-    write: Option<ArgU32>,
-    // TODO: read, write with stdout, stdin
+    tag: Vec<String>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct ArgDevice {
-    bus: u8,
-    addr: u8,
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "completions")]
+/// Print shell completion script
+#[argh(example = "{command_name} bash > /etc/bash_completion.d/rtl8152-led-ctrl")]
+struct CmdCompletions {
+    /// shell to complete for, "bash", "zsh" or "fish"
+    #[argh(positional)]
+    shell: completions::Shell,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct ArgProduct {
-    vid: u16,
-    pid: u16,
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "versions")]
+/// List known chip version codes, without accessing devices
+#[argh(note = "Version code is the high word of PLA register 0xe610 masked with 0x7cf0.")]
+struct CmdVersions {}
+
+with_selectors! {
+    #[derive(FromArgs, PartialEq, Debug)]
+    #[argh(subcommand, name = "reg")]
+    /// Read/write register directly
+    struct CmdReg {
+        /// register type, "pla" or "usb", defaults to "pla"
+        #[argh(option, long = "type")]
+        ty: Option<RegType>,
+
+        /// register offset, e.g. 0xdd90 for LED configuration,
+        /// required unless `--scan-range` is given
+        #[argh(option)]
+        offset: Option<ArgU16>,
+
+        /// register width, 8, 16 or 32, defaults to 32
+        #[argh(option)]
+        width: Option<ArgWidth>,
+
+        /// format of value read, "hex", "dec" or "bin", defaults to "hex"
+        #[argh(option)]
+        raw_format: Option<ArgRawFormat>,
+
+        /// write value to register, e.g. 0xe0087, "-" to read the value from stdin
+        #[argh(option)]
+        write: Option<ArgWriteValue>,
+
+        /// write 0 to register, shorthand for `--write 0`
+        #[argh(switch)]
+        clear: bool,
+
+        /// EXPERT, read each dword in register offset range start:end (end
+        /// exclusive), e.g. 0xdd00:0xde00, for locating registers of unsupported
+        /// chips. Reading registers may have side effects, requires `--force`
+        #[argh(option)]
+        scan_range: Option<ArgRange>,
+
+        /// EXPERT, with `--scan-range`, write the marker to each dword then read it
+        /// back and restore the original value. POTENTIALLY DESTRUCTIVE, writing to
+        /// unknown registers may hang or brick the device until power cycled
+        #[argh(option)]
+        scan_marker: Option<ArgU32>,
+
+        /// confirm potentially destructive operations, i.e. `--scan-range` and
+        /// writing to registers flagged as risky, see README for flagged ranges
+        #[argh(switch)]
+        force: bool,
+
+        /// print time taken by each register access to stderr, and total time
+        /// taken with `--scan-range`
+        #[argh(switch)]
+        measure: bool,
+
+        /// print value read as raw little-endian bytes of `--width` instead of
+        /// text, for piping into `xxd` or a file
+        #[argh(switch)]
+        binary: bool,
+    }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ArgDevices(Vec<DeviceAddr>);
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct ArgLink {
     link10: bool,
@@ -202,6 +695,16 @@ struct ArgLink {
     link1000: bool,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ArgLedIndex(u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ArgLedMode(u8);
+
+/// Empty for any class
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ArgClasses(Vec<u8>);
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum ArgWidth {
     Dword,
@@ -209,54 +712,76 @@ enum ArgWidth {
     Byte,
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum ArgRawFormat {
+    #[default]
+    Hex,
+    Dec,
+    Bin,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct ArgU16(u16);
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ArgRange {
+    start: u16,
+    end: u16,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct ArgU32(u32);
 
-impl FromStr for ArgDevice {
-    type Err = String;
-    fn from_str(s: &str) -> Result<Self, String> {
-        let Some((bus, addr)) = s.split_once(':') else {
-            return Err("invalid format, supply bus_num:dev_num instead".to_string());
-        };
-        let Ok(bus) = u8::from_str(bus) else {
-            return Err("failed to parse bus number".to_string());
-        };
-        let Ok(addr) = u8::from_str(addr) else {
-            return Err("failed to parse device number".to_string());
-        };
+/// Value to write, "-" reads it from stdin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArgWriteValue {
+    Value(u32),
+    Stdin,
+}
 
-        Ok(ArgDevice { bus, addr })
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArgSource {
+    Current,
+    Default,
+    Raw(u32),
 }
 
-impl FromStr for ArgProduct {
+impl FromStr for ArgDevices {
     type Err = String;
     fn from_str(s: &str) -> Result<Self, String> {
-        let Some((vid, pid)) = s.split_once(':') else {
-            return Err("invalid format, supply vid:pid instead".to_string());
-        };
-        let Ok(vid) = u16::from_str_radix(vid, 16) else {
-            return Err("failed to parse vendor ID".to_string());
-        };
-        let Ok(pid) = u16::from_str_radix(pid, 16) else {
-            return Err("failed to parse product ID".to_string());
-        };
+        let mut res = Vec::new();
+        for device in s.split(',') {
+            match DeviceAddr::from_str(device) {
+                Ok(device) => res.push(device),
+                Err(e) => return Err(format!("{} in device \"{}\"", e, device)),
+            }
+        }
+        Ok(ArgDevices(res))
+    }
+}
 
-        Ok(ArgProduct { vid, pid })
+impl ArgLink {
+    const NONE: Self = Self {
+        link10: false,
+        link100: false,
+        link1000: false,
+    };
+}
+
+impl From<ArgLink> for led::LedLinks {
+    fn from(value: ArgLink) -> Self {
+        Self {
+            link10: value.link10,
+            link100: value.link100,
+            link1000: value.link1000,
+        }
     }
 }
 
 impl FromStr for ArgLink {
     type Err = String;
     fn from_str(s: &str) -> Result<Self, String> {
-        let mut res = ArgLink {
-            link10: false,
-            link100: false,
-            link1000: false,
-        };
+        let mut res = ArgLink::NONE;
 
         let links = s.split_terminator(',');
         for link in links {
@@ -272,6 +797,67 @@ impl FromStr for ArgLink {
     }
 }
 
+impl FromStr for ArgLedIndex {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "0" | "1" | "2" => Ok(Self(s.parse().unwrap())),
+            unknown => Err(format!("invalid LED index {}", unknown)),
+        }
+    }
+}
+
+impl FromStr for ArgLedMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.parse() {
+            Ok(mode @ 0..=7) => Ok(Self(mode)),
+            _ => Err("--led-mode must be 0-7".to_string()),
+        }
+    }
+}
+
+impl FromStr for ArgClasses {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        if s == "any" {
+            return Ok(Self(Vec::new()));
+        }
+        let mut res = Vec::new();
+        for class in s.split(',') {
+            let Ok(class) = u8::from_str_radix(class, 16) else {
+                return Err(format!("invalid class code {}", class));
+            };
+            res.push(class);
+        }
+        Ok(Self(res))
+    }
+}
+
+impl FromStr for ArgRawFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let res = match s {
+            "hex" => Self::Hex,
+            "dec" => Self::Dec,
+            "bin" => Self::Bin,
+            unknown => return Err(format!("invalid raw format {}", unknown)),
+        };
+        Ok(res)
+    }
+}
+
+impl ArgRawFormat {
+    /// Formats value padded to `bits` in hexadecimal and binary
+    fn format(self, value: u32, bits: usize) -> String {
+        match self {
+            Self::Hex => format!("0x{:0width$x}", value, width = bits / 4),
+            Self::Dec => value.to_string(),
+            Self::Bin => format!("0b{:0width$b}", value, width = bits),
+        }
+    }
+}
+
 impl FromStr for ArgWidth {
     type Err = String;
     fn from_str(s: &str) -> Result<Self, String> {
@@ -292,6 +878,41 @@ impl FromStr for ArgU16 {
     }
 }
 
+impl FromStr for ArgRange {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let Some((start, end)) = s.split_once(':') else {
+            return Err("invalid format, supply start:end instead".to_string());
+        };
+        let Ok(start) = parse_int::parse(start) else {
+            return Err("failed to parse start offset".to_string());
+        };
+        let Ok(end) = parse_int::parse(end) else {
+            return Err("failed to parse end offset".to_string());
+        };
+        if start > end {
+            return Err("start offset is greater than end offset".to_string());
+        }
+
+        Ok(ArgRange { start, end })
+    }
+}
+
+impl FromStr for ArgSource {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "current" => Ok(ArgSource::Current),
+            "default" => Ok(ArgSource::Default),
+            s => match s.strip_prefix("raw:").map(parse_int::parse) {
+                Some(Ok(value)) => Ok(ArgSource::Raw(value)),
+                Some(Err(_)) => Err("failed to parse raw value".to_string()),
+                None => Err("expect current, default or raw:<value>".to_string()),
+            },
+        }
+    }
+}
+
 impl FromStr for ArgU32 {
     type Err = ParseIntError;
     fn from_str(s: &str) -> Result<Self, ParseIntError> {
@@ -299,128 +920,260 @@ impl FromStr for ArgU32 {
     }
 }
 
-impl CmdSet {
-    fn update_led_config(&self, config: &mut led::LedGlobalConfig, default: bool) {
-        fn update_led_x<const I: u8>(
-            link: Option<ArgLink>,
-            act: Option<bool>,
-            reverse: Option<bool>,
-            led: &mut led::LedConfig<I>,
-            default: bool,
-        ) {
-            if let Some(link) = link {
-                led.link10 = link.link10;
-                led.link100 = link.link100;
-                led.link1000 = link.link1000;
-            } else if default {
-                led.link10 = I == 0;
-                led.link100 = I == 0;
-                led.link1000 = I == 0;
-            }
-            if let Some(act) = act {
-                led.activity = act;
-            } else if default {
-                led.activity = I == 1;
-            }
-            if let Some(reverse) = reverse {
-                led.high_active = reverse;
-            } else if default {
-                led.high_active = false;
+impl FromStr for ArgWriteValue {
+    type Err = ParseIntError;
+    fn from_str(s: &str) -> Result<Self, ParseIntError> {
+        match s {
+            "-" => Ok(Self::Stdin),
+            s => Ok(Self::Value(parse_int::parse(s)?)),
+        }
+    }
+}
+
+impl ArgWriteValue {
+    /// Resolves value, reading a line from stdin for "-"
+    fn resolve(self) -> Result<u32> {
+        match self {
+            Self::Value(value) => Ok(value),
+            Self::Stdin => {
+                let mut line = String::new();
+                io::stdin()
+                    .read_line(&mut line)
+                    .context("reading value from stdin")?;
+                parse_int::parse(line.trim())
+                    .map_err(|_| Error::InvalidArgs("invalid value from stdin"))
             }
         }
+    }
+}
+
+impl CmdSet {
+    /// Whether any option setting individual LED fields is supplied
+    fn has_field_options(&self) -> bool {
+        self.led0_link.is_some()
+            || self.led1_link.is_some()
+            || self.led2_link.is_some()
+            || self.led0_act.is_some()
+            || self.led1_act.is_some()
+            || self.led2_act.is_some()
+            || self.led0_reverse.is_some()
+            || self.led1_reverse.is_some()
+            || self.led2_reverse.is_some()
+            || self.reverse_all.is_some()
+            || self.act_all.is_some()
+            || self.interval.is_some()
+            || self.duty_cycle.is_some()
+    }
+
+    /// Fills options not given on command line from profile selected by
+    /// `--profile`, switches are enabled if set in either
+    fn merge_profile(mut self) -> Result<Self> {
+        let Some(name) = self.profile.take() else {
+            return Ok(self);
+        };
+        let path = match self.profiles.take() {
+            Some(path) => path,
+            None => default_profiles_path().ok_or(Error::InvalidArgs(
+                "cannot locate profiles file, pass --profiles",
+            ))?,
+        };
+        let profiles = profile::load_profiles(&path)?;
+        let found = profile::find(&profiles, &name)?;
+        let args = found.args.iter().map(String::as_str).collect::<Vec<_>>();
+        let base = CmdSet::from_args(&["set"], &args)
+            .map_err(|e| Error::Profile(format!("profile {}: {}", name, e.output.trim_end())))?;
+        if base.profile.is_some() || base.profiles.is_some() {
+            return Err(Error::Profile(format!(
+                "profile {}: nested --profile or --profiles is not allowed",
+                name
+            )));
+        }
+
+        macro_rules! merge {
+            (options: $($opt:ident),*; switches: $($switch:ident),*; lists: $($list:ident),*) => {
+                $(self.$opt = self.$opt.or(base.$opt);)*
+                $(self.$switch |= base.$switch;)*
+                $(self.$list.extend(base.$list);)*
+            };
+        }
+        merge!(
+            options: device, product, name, usb_path, match_mode, source,
+                led0_link, led1_link, led2_link, led0_act, led1_act, led2_act,
+                led0_reverse, led1_reverse, led2_reverse, reverse_all, act_all,
+                interval, duty_cycle, raw, preset, presets, config_dir, led_mode,
+                apply_if_changed_from, confirm_value, write_verify_retries, delay_ms,
+                interval_ms;
+            switches: once_per_bus, strict, no_default, clamp_reserved, preserve_reserved, split_write,
+                dry, explain, force, all, apply_and_watch;
+            lists: exclude, exclude_product
+        );
+        Ok(self)
+    }
+
+    fn update_led_config(&self, config: &mut led::LedGlobalConfig, default: Option<Version>) {
+        if let Some(version) = default {
+            config.apply_defaults_for(version);
+        }
+        if let Some(reverse) = self.reverse_all {
+            config.led_0.high_active = reverse;
+            config.led_1.high_active = reverse;
+            config.led_2.high_active = reverse;
+        }
 
         update_led_x(
             self.led0_link,
             self.led0_act,
             self.led0_reverse,
             &mut config.led_0,
-            default,
         );
         update_led_x(
             self.led1_link,
             self.led1_act,
             self.led1_reverse,
             &mut config.led_1,
-            default,
         );
         update_led_x(
-            self.led1_link,
-            self.led1_act,
-            self.led1_reverse,
+            self.led2_link,
+            self.led2_act,
+            self.led2_reverse,
             &mut config.led_2,
-            default,
         );
 
         if let Some(act_all) = self.act_all {
             config.all_link_activity = act_all;
-        } else if default {
-            config.all_link_activity = false;
         }
         if let Some(interval) = self.interval {
             config.blink_interval =
                 led::BlinkInterval::from_num(interval).unwrap_or(led::BlinkInterval::ILink);
-        } else if default {
-            config.blink_interval = led::BlinkInterval::ILink;
         }
         if let Some(duty_cycle) = self.duty_cycle {
             config.blink_duty_cycle =
                 led::BlinkDutyCycle::from_num(duty_cycle).unwrap_or(led::BlinkDutyCycle::R75);
-        } else if default {
-            config.blink_duty_cycle = led::BlinkDutyCycle::R50;
         }
     }
-}
 
-fn filter_r8152_devices(
-    bus_port: Option<ArgDevice>,
-    vid_pid: Option<ArgProduct>,
-    once: bool,
-) -> Result<Vec<rusb::Device<rusb::GlobalContext>>> {
-    let mut res = Vec::new();
-    for device in rusb::devices()?.iter() {
-        let mut bus_port_matches = false;
-        if let Some(ArgDevice { bus, addr: port }) = bus_port {
-            bus_port_matches = device.bus_number() == bus && device.address() == port;
-            if !bus_port_matches {
-                continue;
+    /// Notes on LEDs where opinionated default fills in a trigger besides the
+    /// one explicitly set, which might not be intended
+    fn default_advisories(&self) -> Vec<String> {
+        fn check_led_x<const I: u8>(
+            link: Option<ArgLink>,
+            act: Option<bool>,
+            default: &led::LedConfig<I>,
+            res: &mut Vec<String>,
+        ) {
+            let default_link = default.links() != led::LedLinks::default();
+            if act.is_some() && link.is_none() && default_link {
+                res.push(format!(
+                    "LED {} also lights on link by default, pass `--led{}-link 0` or `--no-default` to disable",
+                    I, I
+                ));
             }
-        }
-
-        let device_desc = device.device_descriptor()?;
-        if let Some(ArgProduct { vid, pid }) = vid_pid {
-            if vid != device_desc.vendor_id() || pid != device_desc.product_id() {
-                continue;
+            if link.is_some() && act.is_none() && default.activity {
+                res.push(format!(
+                    "LED {} also blinks on activity by default, pass `--led{}-act false` or `--no-default` to disable",
+                    I, I
+                ));
             }
         }
 
-        let matches = RTL8152_DEVICE_VID_PIDS
-            .iter()
-            .any(|&(vid, pid)| device_desc.vendor_id() == vid && device_desc.product_id() == pid);
-        if matches {
+        let default = led::LedGlobalConfig::opinionated_default();
+        let mut res = Vec::new();
+        check_led_x(self.led0_link, self.led0_act, &default.led_0, &mut res);
+        check_led_x(self.led1_link, self.led1_act, &default.led_1, &mut res);
+        check_led_x(self.led2_link, self.led2_act, &default.led_2, &mut res);
+        res
+    }
+}
+
+fn update_led_x<const I: u8>(
+    link: Option<ArgLink>,
+    act: Option<bool>,
+    reverse: Option<bool>,
+    led: &mut led::LedConfig<I>,
+) {
+    if let Some(link) = link {
+        led.set_links(link.into());
+    }
+    if let Some(act) = act {
+        led.activity = act;
+    }
+    if let Some(reverse) = reverse {
+        led.high_active = reverse;
+    }
+}
+
+impl CmdDiff {
+    fn has_selectors(&self) -> bool {
+        self.filter() != Filter::default()
+    }
+}
+
+/// Devices of known vendors but not in `scan::RTL8152_DEVICE_VID_PIDS`, reads
+/// descriptors only
+fn near_miss_devices() -> Result<Vec<rusb::Device<rusb::GlobalContext>>> {
+    let mut res = Vec::new();
+    for device in rusb::devices()?.iter() {
+        let Ok(desc) = device.device_descriptor() else {
+            continue;
+        };
+        let (vid, pid) = (desc.vendor_id(), desc.product_id());
+        let known_vendor = scan::RTL8152_DEVICE_VID_PIDS.iter().any(|&(v, _)| v == vid);
+        let supported = scan::RTL8152_DEVICE_VID_PIDS.contains(&(vid, pid));
+        if known_vendor && !supported {
             res.push(device);
-            if once {
-                break;
-            }
         }
+    }
+    res.sort_by_key(|device| (device.bus_number(), device.address()));
+    Ok(res)
+}
 
-        if bus_port_matches {
-            break;
+/// Opened device, prints transfer statistics on drop with `-v -v`
+struct OpenedCtrl {
+    ctrl: CtrlDevice,
+    verbose: u8,
+}
+
+impl Deref for OpenedCtrl {
+    type Target = CtrlDevice;
+    fn deref(&self) -> &Self::Target {
+        &self.ctrl
+    }
+}
+
+impl Drop for OpenedCtrl {
+    fn drop(&mut self) {
+        if self.verbose > 1 {
+            let stats = self.ctrl.stats();
+            let device = self.ctrl.handle().device();
+            eprintln!(
+                "Bus({:03}:{:03}) control transfers: {} reads, {} writes, {} retries",
+                device.bus_number(),
+                device.address(),
+                stats.reads,
+                stats.writes,
+                stats.retries
+            );
         }
     }
+}
 
-    Ok(res)
+fn open_ctrl(device: &rusb::Device<rusb::GlobalContext>, opts: &Options) -> Result<OpenedCtrl> {
+    Ok(OpenedCtrl {
+        ctrl: scan::open(device, opts)?,
+        verbose: opts.verbose,
+    })
 }
 
-fn print_device_line(ctrl: &CtrlDevice<rusb::GlobalContext>) -> Result<()> {
+fn print_device_line(out: &mut dyn Write, ctrl: &CtrlDevice) -> Result<()> {
     let device = ctrl.handle().device();
     let desc = device.device_descriptor()?;
     let vendor = ctrl.handle().read_manufacturer_string_ascii(&desc)?;
     let product = ctrl.handle().read_product_string_ascii(&desc)?;
     let serial = ctrl.handle().read_serial_number_string_ascii(&desc)?;
-    let version = ctrl.version()?;
 
-    println!(
-        "Bus({:03}:{:03}) ID({:04x}:{:04x}) {} {} ({}) Ver({:?})",
+    writeln!(
+        out,
+        "Bus({:03}:{:03}) ID({:04x}:{:04x}) {} {} ({}) Ver({}) MAC({})",
         device.bus_number(),
         device.address(),
         desc.vendor_id(),
@@ -428,176 +1181,1810 @@ fn print_device_line(ctrl: &CtrlDevice<rusb::GlobalContext>) -> Result<()> {
         vendor,
         product,
         serial,
-        version
-    );
+        ctrl.version()?,
+        mac_str(ctrl.mac_address()?)
+    )?;
 
     Ok(())
 }
 
-fn print_led_x_config<const I: u8>(
-    ident: usize,
-    config: &led::LedConfig<I>,
-    global: &led::LedGlobalConfig,
-) {
-    println!("{:ident$}LED {}:", "", I, ident = ident);
+/// Same as [`print_device_line`], for a device scanned
+fn print_device_info_line(out: &mut dyn Write, info: &scan::DeviceInfo) -> Result<()> {
+    writeln!(
+        out,
+        "Bus({:03}:{:03}) ID({:04x}:{:04x}) {} {} ({}) Ver({}) MAC({})",
+        info.bus,
+        info.addr,
+        info.vid,
+        info.pid,
+        info.manufacturer,
+        info.product,
+        info.serial,
+        info.version,
+        mac_str(info.mac)
+    )?;
 
-    let mut link = Vec::new();
-    if config.link10 {
-        link.push("10Mbps".to_string());
-    }
-    if config.link100 {
-        link.push("100Mbps".to_string())
-    }
-    if config.link1000 {
-        link.push("1000Mbps".to_string())
-    }
-    let link = if link.is_empty() {
-        "Not triggered".to_string()
-    } else {
-        link.join(", ")
-    };
-    println!("{:ident$}Link: {}", "", link, ident = ident + 2);
-
-    let act_all = (!config.link10 && !config.link100 && !config.link1000
-        || global.all_link_activity)
-        && config.activity;
-    let act = if act_all {
-        "Blink on all speed of links"
-    } else if config.activity {
-        "Blink on selected links"
-    } else {
-        "Not triggered"
-    };
-    println!("{:ident$}Activity: {}", "", act, ident = ident + 2);
+    Ok(())
+}
 
-    println!(
-        "{:ident$}Light: {}",
-        "",
-        if config.high_active {
-            "Reversed"
-        } else {
-            "Not reversed"
-        },
-        ident = ident + 2
-    );
+fn mac_str(mac: Option<[u8; 6]>) -> String {
+    match mac {
+        Some(mac) => mac
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .join(":"),
+        None => "not programmed".to_string(),
+    }
 }
 
-fn print_led_config(config: &led::LedGlobalConfig) {
-    let ident = 2;
-    print_led_x_config(ident, &config.led_0, config);
-    print_led_x_config(ident, &config.led_1, config);
-    print_led_x_config(ident, &config.led_2, config);
+fn print_device_line_unopened(
+    out: &mut dyn Write,
+    device: &rusb::Device<rusb::GlobalContext>,
+) -> Result<()> {
+    let desc = device.device_descriptor()?;
 
-    println!(
-        "{:ident$}Blink interval: {}",
-        "",
-        config.blink_interval,
-        ident = ident
-    );
-    println!(
-        "{:ident$}Blink duty cycle(ratio): {}",
-        "",
-        config.blink_duty_cycle,
-        ident = ident
-    );
-    println!(
-        "{:ident$}Raw register value: 0x{:05x}",
-        "",
-        config.to_raw(),
-        ident = ident
-    );
+    writeln!(
+        out,
+        "Bus({:03}:{:03}) ID({:04x}:{:04x})",
+        device.bus_number(),
+        device.address(),
+        desc.vendor_id(),
+        desc.product_id(),
+    )?;
+
+    Ok(())
+}
+
+fn print_interfaces(
+    out: &mut dyn Write,
+    ident: usize,
+    config: &rusb::ConfigDescriptor,
+) -> io::Result<()> {
+    for interface in config.interfaces() {
+        for desc in interface.descriptors() {
+            writeln!(
+                out,
+                "{:ident$}Interface {} Alt({}): Class({:02x}) SubClass({:02x}) Protocol({:02x})",
+                "",
+                desc.interface_number(),
+                desc.setting_number(),
+                desc.class_code(),
+                desc.sub_class_code(),
+                desc.protocol_code(),
+                ident = ident
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn print_descriptors(
+    out: &mut dyn Write,
+    device: &rusb::Device<rusb::GlobalContext>,
+) -> Result<()> {
+    let ident = 2;
+    let desc = device.device_descriptor()?;
+    writeln!(
+        out,
+        "{:ident$}Device: bcdUSB({}) bcdDevice({}) Class({:02x}) SubClass({:02x}) Protocol({:02x}) MaxPacketSize0({}) NumConfigs({})",
+        "",
+        desc.usb_version(),
+        desc.device_version(),
+        desc.class_code(),
+        desc.sub_class_code(),
+        desc.protocol_code(),
+        desc.max_packet_size(),
+        desc.num_configurations(),
+        ident = ident
+    )?;
+    for i in 0..desc.num_configurations() {
+        let config = device.config_descriptor(i)?;
+        writeln!(
+            out,
+            "{:ident$}Config {}: NumInterfaces({}) MaxPower({}mA) SelfPowered({}) RemoteWakeup({})",
+            "",
+            config.number(),
+            config.num_interfaces(),
+            config.max_power(),
+            config.self_powered(),
+            config.remote_wakeup(),
+            ident = ident
+        )?;
+        print_interfaces(out, ident + 2, &config)?;
+    }
+    Ok(())
+}
+
+fn led_act_str<const I: u8>(config: &led::LedConfig<I>, global: &led::LedGlobalConfig) -> String {
+    config.activity_mode(global.all_link_activity).to_string()
+}
+
+fn print_led_x_diff<const I: u8>(
+    ident: usize,
+    old: &led::LedConfig<I>,
+    new: &led::LedConfig<I>,
+    global: &led::LedGlobalConfig,
+) {
+    println!("{:ident$}LED {}:", "", I, ident = ident);
+
+    let fields = [
+        ("Link", old.link_str(), new.link_str()),
+        (
+            "Activity",
+            led_act_str(old, global),
+            led_act_str(new, global),
+        ),
+        (
+            "Light",
+            old.light_str().to_string(),
+            new.light_str().to_string(),
+        ),
+    ];
+    for (name, old, new) in fields {
+        if old == new {
+            println!("{:ident$}{}: {}", "", name, old, ident = ident + 2);
+        } else {
+            println!(
+                "{:ident$}{}: {} -> {}",
+                "",
+                name,
+                old,
+                new,
+                ident = ident + 2
+            );
+        }
+    }
+}
+
+fn print_led_config(out: &mut dyn Write, config: &led::LedGlobalConfig) -> io::Result<()> {
+    print_led_config_as(out, config, ArgRawFormat::Hex)
+}
+
+fn print_led_config_as(
+    out: &mut dyn Write,
+    config: &led::LedGlobalConfig,
+    raw_format: ArgRawFormat,
+) -> io::Result<()> {
+    let ident = 2;
+    let mut led = None;
+    for mut field in config.fields() {
+        if field.name == led::RAW_FIELD {
+            field.value = raw_format.format(config.to_raw(), 20);
+        }
+        if field.led != led {
+            led = field.led;
+            if let Some(i) = led {
+                writeln!(out, "{:ident$}LED {}:", "", i, ident = ident)?;
+            }
+        }
+        let ident = if field.led.is_some() {
+            ident + 2
+        } else {
+            ident
+        };
+        writeln!(
+            out,
+            "{:ident$}{}: {}",
+            "",
+            field.name,
+            field.value,
+            ident = ident
+        )?;
+    }
+    Ok(())
+}
+
+fn print_led_feature_config(out: &mut dyn Write, config: &led::LedFeatureConfig) -> io::Result<()> {
+    writeln!(out, "  LED mode: {}", config.mode)
+}
+
+/// Prints devices in canonical form for tracking changes over time, devices
+/// are sorted by stable ID and fields by key, bus and device numbers are left
+/// out as they change across reboots
+fn show_normalized(
+    out: &mut dyn Write,
+    filter: &Filter,
+    opts: &Options,
+    force: bool,
+) -> Result<()> {
+    let mut infos = scan::scan(filter, opts, force)?;
+    infos.sort_by(|a, b| a.stable_id.cmp(&b.stable_id));
+    for info in infos {
+        let mut lines = vec![
+            format!("product = {:04x}:{:04x}", info.vid, info.pid),
+            format!("version = {:?}", info.version),
+        ];
+        for field in info.led_config.fields() {
+            let name = field
+                .name
+                .to_lowercase()
+                .replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+                .trim_end_matches('_')
+                .to_string();
+            let key = match field.led {
+                Some(i) => format!("led{}.{}", i, name),
+                None => name,
+            };
+            lines.push(format!("{} = {}", key, field.value));
+        }
+        lines.sort();
+        writeln!(out, "[{}]", info.stable_id)?;
+        for line in lines {
+            writeln!(out, "{}", line)?;
+        }
+    }
+    Ok(())
 }
 
-fn handle_cmd_show(cmd: CmdShow) -> Result<()> {
-    let devices = filter_r8152_devices(cmd.device, cmd.product, false)?;
-    for device in devices {
-        let ctrl = CtrlDevice::new(device.open()?)?;
-        print_device_line(&ctrl)?;
-        let led_config = led::LedGlobalConfig::read_from(&ctrl)?;
-        print_led_config(&led_config);
+/// Prints devices grouped by identical product, version and LED register value
+fn show_groups(out: &mut dyn Write, filter: &Filter, opts: &Options, force: bool) -> Result<()> {
+    let mut groups: Vec<(scan::DeviceInfo, Vec<String>)> = Vec::new();
+    for info in scan::scan(filter, opts, force)? {
+        let id = format!("{:03}:{:03}", info.bus, info.addr);
+        let group = groups.iter_mut().find(|(first, _)| {
+            (
+                first.vid,
+                first.pid,
+                first.version,
+                first.led_config.to_raw(),
+            ) == (info.vid, info.pid, info.version, info.led_config.to_raw())
+        });
+        match group {
+            Some((_, ids)) => ids.push(id),
+            None => groups.push((info, vec![id])),
+        }
+    }
+
+    for (info, ids) in groups {
+        writeln!(
+            out,
+            "ID({:04x}:{:04x}) {} {} Ver({}) Count({}) Bus({})",
+            info.vid,
+            info.pid,
+            info.manufacturer,
+            info.product,
+            info.version,
+            ids.len(),
+            ids.join(", ")
+        )?;
+        print_led_config(out, &info.led_config)?;
     }
     Ok(())
 }
 
-fn handle_cmd_set(cmd: CmdSet) -> Result<()> {
-    let Some(device) = filter_r8152_devices(cmd.device, cmd.product, true)?.pop() else {
+/// Reads LED register, accepting suspect values if `force`
+fn read_led_config(ctrl: &CtrlDevice, force: bool) -> Result<led::LedGlobalConfig> {
+    if force {
+        led::LedGlobalConfig::read_from_unchecked(ctrl)
+    } else {
+        led::LedGlobalConfig::read_from(ctrl)
+    }
+}
+
+/// Output of `show`, writes to stdout and a copy to the dump file if any
+struct Report {
+    stdout: io::Stdout,
+    dump: Option<io::BufWriter<std::fs::File>>,
+}
+
+impl Write for Report {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdout.write_all(buf)?;
+        if let Some(dump) = &mut self.dump {
+            dump.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdout.flush()?;
+        if let Some(dump) = &mut self.dump {
+            dump.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "toml")]
+fn write_config_out(path: &std::path::Path, filter: &Filter, opts: &Options) -> Result<()> {
+    let Some(device) = scan::filter_devices(filter, opts, true)
+        .context("enumerating devices")?
+        .pop()
+    else {
         return Err(Error::NotExist);
     };
+    let ctrl = open_ctrl(&device, opts).context("opening device")?;
+    print_device_line(&mut io::stdout(), &ctrl)?;
+    let led_config = led::LedGlobalConfig::read_from(&ctrl).context("reading LED register")?;
+    let content = format!(
+        "# Raw register value: 0x{:05x}\n{}",
+        led_config.to_raw(),
+        config::ConfigFile::from_led_config(&led_config).to_toml()?
+    );
+    std::fs::write(path, content).context("writing config file")?;
+    println!("  Written to {}", path.display());
+    Ok(())
+}
+
+#[cfg(not(feature = "toml"))]
+fn write_config_out(_path: &std::path::Path, _filter: &Filter, _opts: &Options) -> Result<()> {
+    Err(Error::InvalidArgs(
+        "--config-out requires building with the `toml` feature",
+    ))
+}
+
+#[cfg(feature = "json")]
+fn show_json(out: &mut dyn Write, cmd: &CmdShow, opts: &Options) -> Result<()> {
+    json::write_devices(
+        out,
+        &scan::scan(&cmd.filter(), opts, cmd.force)?,
+        cmd.reserved,
+    )
+}
+
+#[cfg(not(feature = "json"))]
+fn show_json(_out: &mut dyn Write, _cmd: &CmdShow, _opts: &Options) -> Result<()> {
+    Err(Error::InvalidArgs(
+        "--json requires building with the `json` feature",
+    ))
+}
+
+fn handle_cmd_show(cmd: CmdShow, opts: &Options) -> Result<()> {
+    if let Some(path) = &cmd.config_out {
+        return write_config_out(path, &cmd.filter(), opts);
+    }
+    if cmd.json && (cmd.group || cmd.normalize_output || cmd.no_open || cmd.list_unsupported) {
+        return Err(Error::InvalidArgs(
+            "--json is mutually exclusive with --group, --normalize-output, --no-open and --list-unsupported",
+        ));
+    }
+    let presets = if cmd.match_preset {
+        preset::load_presets(cmd.presets.as_deref())?
+    } else {
+        Vec::new()
+    };
+    // created after arguments are checked, not to leave an empty file behind
+    let dump = match &cmd.dump {
+        Some(path) => Some(io::BufWriter::new(
+            std::fs::File::create(path).context("creating dump file")?,
+        )),
+        None => None,
+    };
+    let out = &mut Report {
+        stdout: io::stdout(),
+        dump,
+    };
+    if cmd.json {
+        show_json(out, &cmd, opts)?;
+        out.flush().context("writing dump file")?;
+        return Ok(());
+    }
+    if cmd.normalize_output {
+        show_normalized(out, &cmd.filter(), opts, cmd.force)?;
+        out.flush().context("writing dump file")?;
+        return Ok(());
+    }
+    if cmd.group {
+        show_groups(out, &cmd.filter(), opts, cmd.force)?;
+        out.flush().context("writing dump file")?;
+        return Ok(());
+    }
+    if cmd.no_open {
+        let devices =
+            scan::filter_devices(&cmd.filter(), opts, false).context("enumerating devices")?;
+        for device in devices {
+            print_device_line_unopened(out, &device)?;
+            if cmd.dump_descriptors {
+                print_descriptors(out, &device)?;
+            }
+        }
+    } else {
+        for info in scan::scan(&cmd.filter(), opts, cmd.force)? {
+            print_device_info(out, &cmd, &info, &presets)?;
+        }
+    }
+    if cmd.list_unsupported {
+        for device in near_miss_devices().context("enumerating devices")? {
+            let desc = device.device_descriptor()?;
+            writeln!(
+                out,
+                "Bus({:03}:{:03}) ID({:04x}:{:04x}) possibly supported, not in table",
+                device.bus_number(),
+                device.address(),
+                desc.vendor_id(),
+                desc.product_id(),
+            )?;
+        }
+    }
+    out.flush().context("writing dump file")?;
+    Ok(())
+}
+
+/// Prints a device scanned in format of `show`
+fn print_device_info(
+    out: &mut Report,
+    cmd: &CmdShow,
+    info: &scan::DeviceInfo,
+    presets: &[preset::Preset],
+) -> Result<()> {
+    print_device_info_line(out, info)?;
+    let led_config = &info.led_config;
+    if cmd.reserved {
+        writeln!(
+            out,
+            "  Reserved bits: 0x{:08x}",
+            led_config.unknown & !led::LED_VALUE_MASK
+        )?;
+        return Ok(());
+    }
+    writeln!(out, "  Stable ID: {}", info.stable_id)?;
+    if let Some(dump) = &mut out.dump {
+        let caps = info.version.capabilities();
+        writeln!(
+            dump,
+            "  Capabilities: 1000Mbps({}) LED feature({})",
+            caps.link1000, caps.led_feature
+        )
+        .context("writing dump file")?;
+    }
+    if cmd.dump_descriptors {
+        print_descriptors(out, &info.device)?;
+    } else if cmd.verbose {
+        print_interfaces(out, 2, &info.device.active_config_descriptor()?)?;
+    }
+    print_led_config_as(out, led_config, cmd.raw_format.unwrap_or_default())?;
+    writeln!(
+        out,
+        "  Opinionated default: {}",
+        if led_config.is_default() { "yes" } else { "no" }
+    )?;
+    if cmd.match_preset {
+        let name = preset::find_matching(presets, led_config).map_or("None", |p| &p.name);
+        writeln!(out, "  Preset: {}", name)?;
+    }
+    if let Some(feature_config) = &info.feature_config {
+        print_led_feature_config(out, feature_config)?;
+    }
+    Ok(())
+}
+
+/// Default location of profiles file, "$XDG_CONFIG_HOME/rtl8152-led-ctrl/profiles"
+/// or "$HOME/.config/rtl8152-led-ctrl/profiles"
+fn default_profiles_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("rtl8152-led-ctrl").join("profiles"))
+}
+
+fn handle_cmd_set(cmd: CmdSet, opts: &Options) -> Result<()> {
+    // selectors of profile apply as well
+    let cmd = cmd.merge_profile()?;
+    let filter = cmd.filter();
+    set_filtered(cmd, &filter, opts)
+}
+
+/// Same as [`handle_cmd_set`] without merging profile, selecting devices by
+/// `filter` instead of selectors of `cmd`
+fn set_filtered(cmd: CmdSet, filter: &Filter, opts: &Options) -> Result<()> {
+    let presets = if cmd.preset.is_some() || cmd.config_dir.is_some() {
+        preset::load_presets(cmd.presets.as_deref())?
+    } else {
+        Vec::new()
+    };
 
-    let ctrl = CtrlDevice::new(device.open()?)?;
-    print_device_line(&ctrl)?;
+    if cmd.explain && !cmd.dry {
+        return Err(Error::InvalidArgs("--explain requires --dry"));
+    }
+    if cmd.raw.is_some() && cmd.has_field_options() {
+        return Err(Error::InvalidArgs(
+            "--raw is mutually exclusive with options of LED fields, \
+             use --source raw:<value> to apply them on a raw value",
+        ));
+    }
+    if cmd.clamp_reserved && cmd.raw.is_none() {
+        return Err(Error::InvalidArgs("--clamp-reserved requires --raw"));
+    }
+    if cmd.delay_ms.is_some() && !cmd.all {
+        return Err(Error::InvalidArgs("--delay-ms requires --all"));
+    }
+    if cmd.apply_and_watch && (cmd.all || cmd.dry) {
+        return Err(Error::InvalidArgs(
+            "--apply-and-watch conflicts with --all and --dry",
+        ));
+    }
+    if cmd.interval_ms.is_some() && !cmd.apply_and_watch {
+        return Err(Error::InvalidArgs(
+            "--interval-ms requires --apply-and-watch",
+        ));
+    }
+    if cmd.clamp_reserved && cmd.preserve_reserved {
+        return Err(Error::InvalidArgs(
+            "--clamp-reserved conflicts with --preserve-reserved",
+        ));
+    }
+    if cmd.source.is_some()
+        && (cmd.raw.is_some() || cmd.preset.is_some() || cmd.config_dir.is_some())
+    {
+        return Err(Error::InvalidArgs(
+            "--source conflicts with --raw, --preset and --config-dir",
+        ));
+    }
+    let source = match cmd.source {
+        Some(ArgSource::Default) if cmd.no_default => {
+            return Err(Error::InvalidArgs(
+                "--source default conflicts with --no-default",
+            ));
+        }
+        Some(source) => source,
+        None if cmd.no_default => ArgSource::Current,
+        None => ArgSource::Default,
+    };
 
-    let led_config = if let Some(raw) = cmd.raw {
-        led::LedGlobalConfig::from_raw(raw.0)
+    if !cmd.all {
+        let Some(device) = scan::filter_devices(filter, opts, true)
+            .context("enumerating devices")?
+            .pop()
+        else {
+            return Err(Error::NotExist);
+        };
+        return set_device(&cmd, &device, &presets, source, opts);
+    }
+
+    let devices = scan::filter_devices(filter, opts, false).context("enumerating devices")?;
+    if devices.is_empty() {
+        return Err(Error::NotExist);
+    }
+    let delay = Duration::from_millis(cmd.delay_ms.unwrap_or(0));
+    let mut failed = 0;
+    for (i, device) in devices.iter().enumerate() {
+        if i > 0 {
+            println!();
+            std::thread::sleep(delay);
+        }
+        let id = format!("{:03}:{:03}", device.bus_number(), device.address());
+        match set_device(&cmd, device, &presets, source, opts) {
+            Ok(()) => println!("Bus({}) done", id),
+            Err(e) => {
+                failed += 1;
+                eprintln!("Bus({}) Error: {}", id, e);
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(Error::SomeFailed {
+            failed,
+            total: devices.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Masks raw value with [`led::LED_VALUE_MASK`], returns the value masked and a
+/// note on reserved bits dropped if any
+fn clamp_reserved(raw: u32) -> (u32, Option<String>) {
+    let dropped = raw & !led::LED_VALUE_MASK;
+    let note = (dropped != 0).then(|| {
+        format!(
+            "dropped reserved bits 0x{:08x} from raw value 0x{:08x}",
+            dropped, raw
+        )
+    });
+    (raw & led::LED_VALUE_MASK, note)
+}
+
+/// Keeps reserved bits of `current` device value, only known fields of
+/// `config` are written
+fn preserve_reserved(config: &mut led::LedGlobalConfig, current: &led::LedGlobalConfig) {
+    if config.unknown != current.unknown {
+        eprintln!(
+            "Note: keeping reserved bits 0x{:08x} of device instead of 0x{:08x}",
+            current.unknown, config.unknown
+        );
+    }
+    config.unknown = current.unknown;
+}
+
+/// Applies `set` to a single device
+fn set_device(
+    cmd: &CmdSet,
+    device: &rusb::Device<rusb::GlobalContext>,
+    presets: &[preset::Preset],
+    source: ArgSource,
+    opts: &Options,
+) -> Result<()> {
+    let ctrl = open_ctrl(device, opts).context("opening device")?;
+    print_device_line(&mut io::stdout(), &ctrl).context("reading device information")?;
+
+    if let Some(ArgU32(expected)) = cmd.apply_if_changed_from {
+        let actual = read_led_config(&ctrl, cmd.force)
+            .context("reading LED register")?
+            .to_raw();
+        if actual != expected {
+            return Err(Error::Precondition { expected, actual });
+        }
+    }
+
+    let device_config = match &cmd.config_dir {
+        Some(dir) if cmd.raw.is_none() && cmd.preset.is_none() => {
+            let stable_id = ctrl.stable_id().context("reading stable ID")?;
+            let found = preset::load_device_config(dir, &stable_id, presets)?;
+            match &found {
+                Some((path, _)) => println!("Using config {}", path.display()),
+                None => println!("No config found for {}, using default", stable_id),
+            }
+            found.map(|(_, config)| config)
+        }
+        _ => None,
+    };
+
+    let mut led_config = if let Some(ArgU32(mut raw)) = cmd.raw {
+        if cmd.clamp_reserved {
+            let note;
+            (raw, note) = clamp_reserved(raw);
+            if let Some(note) = note {
+                eprintln!("Note: {}", note);
+            }
+        }
+        led::LedGlobalConfig::from_raw(raw)
+    } else if let Some(base) = cmd
+        .preset
+        .as_ref()
+        .map(|name| preset::find(presets, name).map(|p| p.config.clone()))
+        .transpose()?
+        .or(device_config)
+    {
+        let mut config = read_led_config(&ctrl, cmd.force).context("reading LED register")?;
+        let unknown = config.unknown;
+        config = base;
+        config.unknown = unknown;
+        cmd.update_led_config(&mut config, None);
+        config
     } else {
-        let mut config = led::LedGlobalConfig::read_from(&ctrl)?;
-        cmd.update_led_config(&mut config, !cmd.no_default);
+        let mut config = match source {
+            ArgSource::Raw(value) => led::LedGlobalConfig::from_raw(value),
+            _ => read_led_config(&ctrl, cmd.force).context("reading LED register")?,
+        };
+        let default = if source == ArgSource::Default {
+            for note in cmd.default_advisories() {
+                eprintln!("Note: {}", note);
+            }
+            Some(ctrl.version()?)
+        } else {
+            None
+        };
+        cmd.update_led_config(&mut config, default);
         config
     };
+    if cmd.preserve_reserved {
+        let current = read_led_config(&ctrl, cmd.force).context("reading LED register")?;
+        preserve_reserved(&mut led_config, &current);
+    }
+
+    print_led_config(&mut io::stdout(), &led_config)?;
+
+    let feature_config = if let Some(ArgLedMode(mode)) = cmd.led_mode {
+        if !ctrl.version()?.capabilities().led_feature {
+            return Err(Error::Unsupported);
+        }
+        let mut config = led::LedFeatureConfig::from_raw((led_config.to_raw() >> 16) as _);
+        config.mode = mode;
+        print_led_feature_config(&mut io::stdout(), &config)?;
+        Some(config)
+    } else {
+        None
+    };
 
-    print_led_config(&led_config);
+    if cmd.dry {
+        print_dry_run_prediction(&ctrl, &led_config, cmd.force)?;
+        if cmd.explain {
+            let current = read_led_config(&ctrl, cmd.force).context("reading LED register")?;
+            println!("\nRegister bits changed:");
+            for line in current.explain_changes(&led_config) {
+                println!("  {}", line);
+            }
+        }
+        println!("\nDry run, LED configuration not set.");
+    } else {
+        let mut retries = cmd.write_verify_retries;
+        loop {
+            if cmd.split_write {
+                led_config.write_split_to(&ctrl)
+            } else {
+                led_config.write_to(&ctrl)
+            }
+            .context("writing LED register")?;
+            let Some(left) = retries else {
+                break;
+            };
+            let actual = read_led_config(&ctrl, cmd.force).context("reading back LED register")?;
+            if actual.fields_eq(&led_config) {
+                break;
+            }
+            if left == 0 {
+                return Err(Error::VerifyMismatch {
+                    expected: led_config.to_raw(),
+                    actual: actual.to_raw(),
+                });
+            }
+            eprintln!(
+                "LED register reads back 0x{:05x}, retrying write",
+                actual.to_raw()
+            );
+            retries = Some(left - 1);
+        }
+        if let Some(config) = feature_config {
+            config
+                .write_to(&ctrl)
+                .context("writing LED feature register")?;
+        }
+        if let Some(ArgU32(expected)) = cmd.confirm_value {
+            let actual = read_led_config(&ctrl, cmd.force)
+                .context("reading back LED register")?
+                .to_raw();
+            if actual != expected {
+                return Err(Error::ConfirmMismatch { expected, actual });
+            }
+        }
+    }
+
+    if cmd.apply_and_watch {
+        println!();
+        let interval = Duration::from_millis(cmd.interval_ms.unwrap_or(1000));
+        watch_led_register(&ctrl, interval, None, None, cmd.force)?;
+    }
+
+    Ok(())
+}
+
+fn print_dry_run_prediction(
+    ctrl: &CtrlDevice,
+    led_config: &led::LedGlobalConfig,
+    force: bool,
+) -> Result<()> {
+    let current = read_led_config(ctrl, force)?.to_raw();
+    let proposed = led_config.to_raw();
+    let reserved = proposed & !led::LED_VALUE_MASK;
+
+    println!("\nCurrent register value: 0x{:05x}", current);
+    if led_config.is_default() {
+        println!("Result equals opinionated default");
+    }
+    let changed_reserved = (proposed ^ current) & !led::LED_VALUE_MASK;
+    if changed_reserved != 0 {
+        println!(
+            "Reserved bits would change from 0x{:08x} to 0x{:08x}, bits outside of known LED fields (mask 0x{:05x}) are likely ignored by hardware",
+            current & !led::LED_VALUE_MASK,
+            reserved,
+            led::LED_VALUE_MASK
+        );
+    }
+
+    Ok(())
+}
+
+fn handle_cmd_update_led(cmd: CmdUpdateLed, opts: &Options) -> Result<()> {
+    let Some(device) = scan::filter_devices(&cmd.filter(), opts, true)?.pop() else {
+        return Err(Error::NotExist);
+    };
+
+    let ctrl = open_ctrl(&device, opts).context("opening device")?;
+    print_device_line(&mut io::stdout(), &ctrl).context("reading device information")?;
+
+    let old_config = read_led_config(&ctrl, cmd.force).context("reading LED register")?;
+    let mut led_config = old_config.clone();
+    let ident = 2;
+    match cmd.led.0 {
+        0 => {
+            update_led_x(cmd.link, cmd.act, cmd.reverse, &mut led_config.led_0);
+            print_led_x_diff(ident, &old_config.led_0, &led_config.led_0, &led_config);
+        }
+        1 => {
+            update_led_x(cmd.link, cmd.act, cmd.reverse, &mut led_config.led_1);
+            print_led_x_diff(ident, &old_config.led_1, &led_config.led_1, &led_config);
+        }
+        _ => {
+            update_led_x(cmd.link, cmd.act, cmd.reverse, &mut led_config.led_2);
+            print_led_x_diff(ident, &old_config.led_2, &led_config.led_2, &led_config);
+        }
+    }
+    println!(
+        "{:ident$}Raw register value: 0x{:05x} -> 0x{:05x}",
+        "",
+        old_config.to_raw(),
+        led_config.to_raw(),
+        ident = ident
+    );
 
     if cmd.dry {
         println!("\nDry run, LED configuration not set.");
     } else {
-        led_config.write_to(&ctrl)?;
+        led_config.write_to(&ctrl).context("writing LED register")?;
     }
 
     Ok(())
 }
 
-fn handle_cmd_reg(cmd: CmdReg) -> Result<()> {
-    let Some(device) = filter_r8152_devices(cmd.device, cmd.product, true)?.pop() else {
+fn handle_cmd_reg(cmd: CmdReg, opts: &Options) -> Result<()> {
+    let write = if cmd.clear {
+        if cmd.write.is_some() {
+            return Err(Error::InvalidArgs(
+                "--clear is mutually exclusive with --write",
+            ));
+        }
+        Some(ArgWriteValue::Value(0))
+    } else {
+        cmd.write
+    };
+    if cmd.binary && (write.is_some() || cmd.scan_range.is_some()) {
+        return Err(Error::InvalidArgs(
+            "--binary is mutually exclusive with --write, --clear and --scan-range",
+        ));
+    }
+    if cmd.binary && cmd.raw_format.is_some() {
+        return Err(Error::InvalidArgs(
+            "--binary is mutually exclusive with --raw-format",
+        ));
+    }
+    let write = write.map(ArgWriteValue::resolve).transpose()?;
+
+    if cmd.scan_range.is_some() {
+        if write.is_some() {
+            return Err(Error::InvalidArgs(
+                "--scan-range is mutually exclusive with --write and --clear",
+            ));
+        }
+        if !cmd.force {
+            return Err(Error::InvalidArgs(
+                "--scan-range may have side effects on the device, pass --force to confirm",
+            ));
+        }
+    } else if cmd.scan_marker.is_some() {
+        return Err(Error::InvalidArgs("--scan-marker requires --scan-range"));
+    }
+
+    if let (Some(_), Some(ArgU16(offset))) = (write, cmd.offset) {
+        let ty = cmd.ty.unwrap_or(RegType::Pla);
+        if let Some(what) = device::risky_register(ty, offset) {
+            eprintln!("Warning: 0x{:04x} is in {} registers", offset, what);
+            if !cmd.force {
+                return Err(Error::InvalidArgs(
+                    "writing to risky register, pass --force to confirm",
+                ));
+            }
+        } else if !led::is_led_register(ty, offset) {
+            eprintln!("Note: 0x{:04x} is outside of LED register", offset);
+        }
+    }
+
+    let Some(device) = scan::filter_devices(&cmd.filter(), opts, true)
+        .context("enumerating devices")?
+        .pop()
+    else {
         return Err(Error::NotExist);
     };
-    let ctrl = CtrlDevice::new(device.open()?)?;
+    let ctrl = open_ctrl(&device, opts).context("opening device")?;
 
     let ty = cmd.ty.unwrap_or(RegType::Pla);
-    let offset = cmd.offset.0;
+    if let Some(range) = cmd.scan_range {
+        return scan_registers(&ctrl, ty, range, cmd.scan_marker, cmd.measure);
+    }
+    let Some(ArgU16(offset)) = cmd.offset else {
+        return Err(Error::InvalidArgs("--offset is required"));
+    };
     let width = cmd.width.unwrap_or(ArgWidth::Dword);
 
-    if let Some(ArgU32(value)) = cmd.write {
-        eprintln!(
-            "writing to 0x{:04x}, value: {:?} 0x{:x}",
-            offset, width, value
-        );
+    let start = Instant::now();
+    if let Some(value) = write {
+        if cmd.clear {
+            eprintln!("clearing 0x{:04x}, width: {:?}", offset, width);
+        } else {
+            eprintln!(
+                "writing to 0x{:04x}, value: {:?} 0x{:x}",
+                offset, width, value
+            );
+        }
         match width {
-            ArgWidth::Byte => ctrl.write_byte(ty, offset, value as _)?,
-            ArgWidth::Word => ctrl.write_word(ty, offset, value as _)?,
-            ArgWidth::Dword => ctrl.write_dword(ty, offset, value as _)?,
+            ArgWidth::Byte => ctrl.write_byte(ty, offset, value as _),
+            ArgWidth::Word => ctrl.write_word(ty, offset, value as _),
+            ArgWidth::Dword => ctrl.write_dword(ty, offset, value as _),
         }
+        .context("writing register")?;
     } else {
-        match width {
-            ArgWidth::Byte => {
-                let value = ctrl.read_byte(ty, offset)?;
-                println!("0x{:02x}", value);
+        let (value, bits) = match width {
+            ArgWidth::Byte => (ctrl.read_byte(ty, offset).map(u32::from), 8),
+            ArgWidth::Word => (ctrl.read_word(ty, offset).map(u32::from), 16),
+            ArgWidth::Dword => (ctrl.read_dword(ty, offset), 32),
+        };
+        let value = value.context("reading register")?;
+        if cmd.binary {
+            let mut stdout = io::stdout();
+            stdout.write_all(&value.to_le_bytes()[..bits / 8])?;
+            stdout.flush()?;
+        } else {
+            println!("{}", cmd.raw_format.unwrap_or_default().format(value, bits));
+        }
+    }
+    if cmd.measure {
+        eprintln!("took {}us", start.elapsed().as_micros());
+    }
+
+    Ok(())
+}
+
+fn scan_registers(
+    ctrl: &CtrlDevice,
+    ty: RegType,
+    range: ArgRange,
+    marker: Option<ArgU32>,
+    measure: bool,
+) -> Result<()> {
+    if range.start % 4 != 0 || range.end % 4 != 0 {
+        return Err(Error::InvalidArgs("scan range must be dword aligned"));
+    }
+
+    let scan_start = Instant::now();
+    for offset in (range.start..range.end).step_by(4) {
+        let start = Instant::now();
+        let value = ctrl.read_dword(ty, offset);
+        if measure {
+            eprintln!("0x{:04x}: took {}us", offset, start.elapsed().as_micros());
+        }
+        let value = match value {
+            Ok(value) => value,
+            Err(e) => {
+                println!("0x{:04x}: {}", offset, e);
+                continue;
+            }
+        };
+        let Some(ArgU32(marker)) = marker else {
+            println!("0x{:04x}: 0x{:08x}", offset, value);
+            continue;
+        };
+
+        let readback = ctrl
+            .write_dword(ty, offset, marker)
+            .and_then(|_| ctrl.read_dword(ty, offset));
+        let restored = ctrl.write_dword(ty, offset, value);
+        match readback {
+            Ok(readback) => println!(
+                "0x{:04x}: 0x{:08x}, marker read back 0x{:08x}",
+                offset, value, readback
+            ),
+            Err(e) => println!("0x{:04x}: 0x{:08x}, marker {}", offset, value, e),
+        }
+        restored.context("restoring register")?;
+    }
+    if measure {
+        eprintln!("total: took {}us", scan_start.elapsed().as_micros());
+    }
+
+    Ok(())
+}
+
+fn handle_cmd_diff(cmd: CmdDiff, opts: &Options) -> Result<()> {
+    let a = match cmd.raw_a {
+        Some(_) if cmd.has_selectors() => {
+            return Err(Error::InvalidArgs(
+                "--raw-a conflicts with device selectors",
+            ));
+        }
+        Some(ArgU32(raw)) => led::LedGlobalConfig::from_raw(raw),
+        None => {
+            let Some(device) = scan::filter_devices(&cmd.filter(), opts, true)
+                .context("enumerating devices")?
+                .pop()
+            else {
+                return Err(Error::NotExist);
+            };
+            let ctrl = open_ctrl(&device, opts).context("opening device")?;
+            print_device_line(&mut io::stdout(), &ctrl)?;
+            read_led_config(&ctrl, cmd.force).context("reading LED register")?
+        }
+    };
+    let ArgU32(raw_b) = cmd.raw_b;
+    let b = led::LedGlobalConfig::from_raw(raw_b);
+
+    let rows = a
+        .fields()
+        .into_iter()
+        .zip(b.fields())
+        .map(|(field_a, field_b)| {
+            let name = match field_a.led {
+                Some(i) => format!("LED {} {}", i, field_a.name),
+                None => field_a.name.to_string(),
+            };
+            (name, field_a.value, field_b.value)
+        })
+        .collect::<Vec<_>>();
+    let name_width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0);
+    let a_width = rows.iter().map(|row| row.1.len()).max().unwrap_or(0);
+    println!("  {:name_width$}  {:a_width$}  B", "Field", "A");
+    for (name, value_a, value_b) in &rows {
+        let mark = if value_a != value_b { '*' } else { ' ' };
+        println!(
+            "{} {:name_width$}  {:a_width$}  {}",
+            mark, name, value_a, value_b
+        );
+    }
+
+    let (raw_a, raw_b) = (a.to_raw(), b.to_raw());
+    let changed = raw_a ^ raw_b;
+    if changed == 0 {
+        println!("\nNo bits changed");
+        return Ok(());
+    }
+    println!("\nChanged bits:");
+    for bit in (0..32).filter(|bit| changed & (1 << bit) != 0) {
+        println!(
+            "  bit {} ({}): {} -> {}",
+            bit,
+            led::bit_name(bit),
+            (raw_a >> bit) & 1,
+            (raw_b >> bit) & 1
+        );
+    }
+    Ok(())
+}
+
+fn handle_cmd_dump(cmd: CmdDump, opts: &Options) -> Result<()> {
+    let ArgU16(offset) = cmd.offset;
+    let ArgU16(length) = cmd.length;
+    let len = length as usize;
+    if offset % 4 != 0 || len % 4 != 0 {
+        return Err(Error::Align {
+            required: 4,
+            offset,
+            len,
+        })
+        .context("dump region");
+    }
+    if offset as usize + len > u16::MAX as _ {
+        return Err(Error::Bound { offset, len }).context("dump region");
+    }
+
+    let Some(device) = scan::filter_devices(&cmd.filter(), opts, true)
+        .context("enumerating devices")?
+        .pop()
+    else {
+        return Err(Error::NotExist);
+    };
+    let ctrl = open_ctrl(&device, opts).context("opening device")?;
+
+    let mut data = vec![0; length as usize];
+    ctrl.read(cmd.ty.unwrap_or(RegType::Pla), offset, &mut data)
+        .context("reading registers")?;
+    print_hexdump(&mut io::stdout(), offset, &data)?;
+    Ok(())
+}
+
+/// Prints `data` as hex dump of 16 bytes per line, with absolute register
+/// offset of each line starting at `base`
+fn print_hexdump(out: &mut dyn Write, base: u16, data: &[u8]) -> io::Result<()> {
+    for (i, line) in data.chunks(16).enumerate() {
+        let hex = line
+            .iter()
+            .enumerate()
+            .map(|(j, b)| {
+                let sep = if j == 8 { "  " } else { " " };
+                format!("{}{:02x}", sep, b)
+            })
+            .collect::<String>();
+        let ascii = line
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect::<String>();
+        writeln!(
+            out,
+            "0x{:04x}:{:<49} |{}|",
+            base as usize + i * 16,
+            hex,
+            ascii
+        )?;
+    }
+    Ok(())
+}
+
+/// Restores LED configuration on drop
+struct LedRestoreGuard<'a> {
+    ctrl: &'a CtrlDevice,
+    config: led::LedGlobalConfig,
+}
+
+impl Drop for LedRestoreGuard<'_> {
+    fn drop(&mut self) {
+        match self.config.write_to(self.ctrl) {
+            Ok(()) => eprintln!("LED configuration restored"),
+            Err(e) => eprintln!("Error: failed to restore LED configuration: {}", e),
+        }
+    }
+}
+
+/// Returns a flag set once Ctrl-C is pressed
+fn interrupt_flag() -> Arc<AtomicBool> {
+    let flag = Arc::new(AtomicBool::new(false));
+    let handler_flag = flag.clone();
+    if let Err(e) = ctrlc::set_handler(move || handler_flag.store(true, Ordering::SeqCst)) {
+        eprintln!("Warning: failed to install Ctrl-C handler: {}", e);
+    }
+    flag
+}
+
+/// Sleeps for given duration, returns false if interrupted
+fn sleep_interruptible(duration: Duration, interrupted: &AtomicBool) -> bool {
+    let deadline = Instant::now() + duration;
+    loop {
+        if interrupted.load(Ordering::SeqCst) {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        std::thread::sleep((deadline - now).min(Duration::from_millis(50)));
+    }
+}
+
+fn handle_cmd_selftest(cmd: CmdSelftest, opts: &Options) -> Result<()> {
+    let Some(device) = scan::filter_devices(&cmd.filter(), opts, true)?.pop() else {
+        return Err(Error::NotExist);
+    };
+
+    let ctrl = open_ctrl(&device, opts).context("opening device")?;
+    print_device_line(&mut io::stdout(), &ctrl).context("reading device information")?;
+
+    let interrupted = interrupt_flag();
+    let step = Duration::from_millis(cmd.step_ms.unwrap_or(1000));
+    let original = read_led_config(&ctrl, cmd.force).context("reading LED register")?;
+    let guard = LedRestoreGuard {
+        ctrl: &ctrl,
+        config: original.clone(),
+    };
+
+    // LEDs without link or activity triggered are lit if polarity is reversed
+    let mut off = original.clone();
+    update_led_x(
+        Some(ArgLink::NONE),
+        Some(false),
+        Some(false),
+        &mut off.led_0,
+    );
+    update_led_x(
+        Some(ArgLink::NONE),
+        Some(false),
+        Some(false),
+        &mut off.led_1,
+    );
+    update_led_x(
+        Some(ArgLink::NONE),
+        Some(false),
+        Some(false),
+        &mut off.led_2,
+    );
+    let mut led0_on = off.clone();
+    led0_on.led_0.high_active = true;
+    let mut led1_on = off.clone();
+    led1_on.led_1.high_active = true;
+    let mut led2_on = off.clone();
+    led2_on.led_2.high_active = true;
+    let mut all_on = off.clone();
+    all_on.led_0.high_active = true;
+    all_on.led_1.high_active = true;
+    all_on.led_2.high_active = true;
+    let mut reversed = original.clone();
+    reversed.led_0.high_active = !reversed.led_0.high_active;
+    reversed.led_1.high_active = !reversed.led_1.high_active;
+    reversed.led_2.high_active = !reversed.led_2.high_active;
+
+    if cmd.led_test_pattern {
+        let steps = [
+            ("LED 0 on", led0_on),
+            ("LED 1 on", led1_on),
+            ("LED 2 on", led2_on),
+        ];
+        println!("Walking LEDs, press Ctrl-C to stop");
+        'walk: loop {
+            for (desc, config) in &steps {
+                println!("{}", desc);
+                config.write_to(&ctrl)?;
+                if !sleep_interruptible(step, &interrupted) {
+                    break 'walk;
+                }
             }
-            ArgWidth::Word => {
-                let value = ctrl.read_word(ty, offset)?;
-                println!("0x{:04x}", value);
+        }
+    } else {
+        let steps = [
+            ("All LEDs off", off),
+            ("LED 0 on", led0_on),
+            ("LED 1 on", led1_on),
+            ("LED 2 on", led2_on),
+            ("All LEDs on", all_on),
+            ("Original configuration reversed", reversed),
+        ];
+        for (desc, config) in steps {
+            println!("{}", desc);
+            config.write_to(&ctrl)?;
+            if !sleep_interruptible(step, &interrupted) {
+                println!("Interrupted");
+                break;
+            }
+        }
+    }
+
+    drop(guard);
+    Ok(())
+}
+
+/// Current UTC time of day in form of "HH:MM:SS.mmm"
+fn utc_time_of_day() -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs() % 86400;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        now.subsec_millis()
+    )
+}
+
+fn handle_cmd_watch(cmd: CmdWatch, opts: &Options) -> Result<()> {
+    if cmd.count.is_some() && cmd.max_changes.is_some() {
+        return Err(Error::InvalidArgs("--count conflicts with --max-changes"));
+    }
+    let max_changes = cmd.count.or(cmd.max_changes);
+
+    let Some(device) = scan::filter_devices(&cmd.filter(), opts, true)?.pop() else {
+        return Err(Error::NotExist);
+    };
+
+    let ctrl = open_ctrl(&device, opts).context("opening device")?;
+    print_device_line(&mut io::stdout(), &ctrl).context("reading device information")?;
+
+    let interval = Duration::from_millis(cmd.interval_ms.unwrap_or(1000));
+    let deadline = cmd
+        .duration
+        .map(|seconds| Instant::now() + Duration::from_secs(seconds));
+    watch_led_register(&ctrl, interval, max_changes, deadline, cmd.force)
+}
+
+/// Polls LED register and prints changed fields until Ctrl-C, `max_changes`
+/// changes or `deadline`, accepting suspect values if `force`
+fn watch_led_register(
+    ctrl: &CtrlDevice,
+    interval: Duration,
+    max_changes: Option<u32>,
+    deadline: Option<Instant>,
+    force: bool,
+) -> Result<()> {
+    let interrupted = interrupt_flag();
+    let mut old = read_led_config(ctrl, force).context("reading LED register")?;
+    println!(
+        "{} Raw register value: 0x{:05x}, press Ctrl-C to stop",
+        utc_time_of_day(),
+        old.to_raw()
+    );
+
+    let mut changes = 0;
+    while max_changes.map_or(true, |max| changes < max) {
+        let wait = match deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(left) if !left.is_zero() => interval.min(left),
+                _ => break,
+            },
+            None => interval,
+        };
+        if !sleep_interruptible(wait, &interrupted) {
+            break;
+        }
+        let new = read_led_config(ctrl, force).context("reading LED register")?;
+        if new.to_raw() == old.to_raw() {
+            continue;
+        }
+        changes += 1;
+        println!(
+            "{} Raw register value: 0x{:05x} -> 0x{:05x}",
+            utc_time_of_day(),
+            old.to_raw(),
+            new.to_raw()
+        );
+        for (old_field, new_field) in old.fields().into_iter().zip(new.fields()) {
+            if old_field.value == new_field.value || old_field.name == led::RAW_FIELD {
+                continue;
             }
-            ArgWidth::Dword => {
-                let value = ctrl.read_dword(ty, offset)?;
-                println!("0x{:08x}", value);
+            let name = match old_field.led {
+                Some(i) => format!("LED {} {}", i, old_field.name),
+                None => old_field.name.to_string(),
+            };
+            println!("  {}: {} -> {}", name, old_field.value, new_field.value);
+        }
+        old = new;
+    }
+    Ok(())
+}
+
+fn handle_cmd_identify(cmd: CmdIdentify, opts: &Options) -> Result<()> {
+    let Some(device) = scan::filter_devices(&cmd.filter(), opts, true)?.pop() else {
+        return Err(Error::NotExist);
+    };
+
+    let ctrl = open_ctrl(&device, opts).context("opening device")?;
+    print_device_line(&mut io::stdout(), &ctrl).context("reading device information")?;
+
+    let interrupted = interrupt_flag();
+    let original = read_led_config(&ctrl, cmd.force).context("reading LED register")?;
+    let guard = LedRestoreGuard {
+        ctrl: &ctrl,
+        config: original.clone(),
+    };
+
+    // LEDs without link or activity triggered are lit if polarity is reversed,
+    // toggling polarity flashes them regardless of link state
+    let mut off = original.clone();
+    update_led_x(
+        Some(ArgLink::NONE),
+        Some(false),
+        Some(false),
+        &mut off.led_0,
+    );
+    update_led_x(
+        Some(ArgLink::NONE),
+        Some(false),
+        Some(false),
+        &mut off.led_1,
+    );
+    update_led_x(
+        Some(ArgLink::NONE),
+        Some(false),
+        Some(false),
+        &mut off.led_2,
+    );
+    let mut on = off.clone();
+    on.led_0.high_active = true;
+    on.led_1.high_active = true;
+    on.led_2.high_active = true;
+
+    let seconds = cmd.seconds.unwrap_or(5);
+    println!("Flashing LEDs for {}s, press Ctrl-C to stop", seconds);
+    let deadline = Instant::now() + Duration::from_secs(seconds);
+    for config in [&on, &off].into_iter().cycle() {
+        if Instant::now() >= deadline {
+            break;
+        }
+        config.write_to(&ctrl)?;
+        if !sleep_interruptible(Duration::from_millis(250), &interrupted) {
+            println!("Interrupted");
+            break;
+        }
+    }
+
+    drop(guard);
+    Ok(())
+}
+
+fn quote_systemd_arg(arg: &str) -> String {
+    let needs_quote = arg.is_empty()
+        || arg
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | ';' | '$' | '%'));
+    if !needs_quote {
+        return arg.to_string();
+    }
+    let mut res = String::from("\"");
+    for c in arg.chars() {
+        match c {
+            '"' | '\\' => {
+                res.push('\\');
+                res.push(c);
             }
+            '$' => res.push_str("$$"),
+            '%' => res.push_str("%%"),
+            c => res.push(c),
         }
     }
+    res.push('"');
+    res
+}
+
+fn handle_cmd_versions() -> Result<()> {
+    for &(code, version) in device::VERSION_CODES {
+        let caps = version.capabilities();
+        println!(
+            "0x{:04x} {:?} {}: {}, {}",
+            code,
+            version,
+            version.name(),
+            if caps.link1000 {
+                "10/100/1000Mbps"
+            } else {
+                "10/100Mbps"
+            },
+            if caps.led_feature {
+                "LED mode"
+            } else {
+                "no LED mode"
+            }
+        );
+    }
+    println!("Other codes are reported as Unknown(code)");
+    Ok(())
+}
+
+fn handle_cmd_systemd_unit(cmd: CmdSystemdUnit) -> Result<()> {
+    let exec = match cmd.exec {
+        Some(exec) => exec,
+        None => std::env::current_exe()
+            .ok()
+            .and_then(|path| path.to_str().map(str::to_string))
+            .unwrap_or_else(|| "rtl8152-led-ctrl".to_string()),
+    };
+
+    let mut args = vec![exec, "set".to_string()];
+    if let Some(ArgDevices(devices)) = &cmd.device {
+        let devices = devices
+            .iter()
+            .map(|device| format!("{}:{}", device.bus, device.addr))
+            .collect::<Vec<_>>();
+        args.push("--device".to_string());
+        args.push(devices.join(","));
+    }
+    if let Some(ProductId { vid, pid }) = cmd.product {
+        args.push("--product".to_string());
+        args.push(format!("{:04x}:{:04x}", vid, pid));
+    }
+    if let Some(name) = cmd.name {
+        args.push("--name".to_string());
+        args.push(name);
+    }
+    if let Some(usb_path) = cmd.usb_path {
+        args.push("--usb-path".to_string());
+        args.push(usb_path);
+    }
+    args.extend(cmd.args);
+
+    let exec_start = args
+        .iter()
+        .map(|arg| quote_systemd_arg(arg))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    println!("# /etc/systemd/system/rtl8152-led-ctrl.service");
+    println!("[Unit]");
+    println!("Description=Set LED configuration of RTL8152 series USB NIC");
+    println!("# wait for USB devices being enumerated");
+    println!("Wants=systemd-udev-settle.service");
+    println!("After=systemd-udev-settle.service");
+    println!("Before=network-pre.target");
+    println!();
+    println!("[Service]");
+    println!("Type=oneshot");
+    println!("RemainAfterExit=yes");
+    println!("ExecStart={}", exec_start);
+    println!();
+    println!("[Install]");
+    println!("WantedBy=multi-user.target");
+
+    Ok(())
+}
+
+fn handle_cmd_gen_udev(cmd: CmdGenUdev) -> Result<()> {
+    if cmd.group.is_none() && cmd.tag.is_empty() {
+        return Err(Error::InvalidArgs(
+            "pass --group or --tag to grant access to devices",
+        ));
+    }
+    let valid = |s: &str| !s.is_empty() && !s.contains(|c: char| c == '"' || c.is_whitespace());
+    if !cmd.group.as_deref().map_or(true, valid) || !cmd.tag.iter().all(|tag| valid(tag)) {
+        return Err(Error::InvalidArgs(
+            "group and tags must be non-empty without quotes or whitespaces",
+        ));
+    }
+
+    let mut action = String::from("MODE=\"0660\"");
+    if let Some(group) = &cmd.group {
+        action.push_str(&format!(", GROUP=\"{}\"", group));
+    }
+    for tag in &cmd.tag {
+        action.push_str(&format!(", TAG+=\"{}\"", tag));
+    }
+
+    println!("# /etc/udev/rules.d/70-rtl8152-led-ctrl.rules");
+    println!("# grant access to RTL8152 series USB NICs for rtl8152-led-ctrl");
+    for &(vid, pid) in scan::RTL8152_DEVICE_VID_PIDS {
+        println!(
+            "SUBSYSTEM==\"usb\", ENV{{DEVTYPE}}==\"usb_device\", ATTR{{idVendor}}==\"{:04x}\", ATTR{{idProduct}}==\"{:04x}\", {}",
+            vid, pid, action
+        );
+    }
 
     Ok(())
 }
 
+fn handle_cmd_completions(cmd: CmdCompletions) -> Result<()> {
+    use argh::SubCommands;
+
+    let subcommands: Vec<_> = CmdEnum::COMMANDS
+        .iter()
+        .map(|info| (info.name, info.description))
+        .collect();
+    completions::write_completions(&mut io::stdout(), cmd.shell, &subcommands)?;
+    Ok(())
+}
+
 fn main() -> Result<()> {
-    let TopArgs { cmd } = argh::from_env();
+    let TopArgs {
+        interface,
+        require_class,
+        verbose,
+        assume_version,
+        no_version_check,
+        delay_after_open,
+        timeout_ms,
+        detach,
+        retries,
+        cmd,
+    } = argh::from_env();
+    let opts = Options {
+        interface,
+        require_class: require_class.map_or_else(
+            || scan::NETWORK_CLASSES.to_vec(),
+            |ArgClasses(classes)| classes,
+        ),
+        verbose,
+        assume_version,
+        no_version_check,
+        delay_after_open: Duration::from_millis(delay_after_open.unwrap_or(0)),
+        timeout: timeout_ms.map_or(device::DEFAULT_TIMEOUT, Duration::from_millis),
+        detach,
+        retries: retries.unwrap_or(device::DEFAULT_RETRIES),
+    };
+    let opts = &opts;
 
     let res = match cmd {
-        CmdEnum::Show(cmd_show) => handle_cmd_show(cmd_show),
-        CmdEnum::Set(cmd_set) => handle_cmd_set(cmd_set),
-        CmdEnum::Reg(cmd_reg) => handle_cmd_reg(cmd_reg),
+        CmdEnum::Show(cmd_show) => handle_cmd_show(cmd_show, opts),
+        CmdEnum::Set(cmd_set) => handle_cmd_set(cmd_set, opts),
+        CmdEnum::Reg(cmd_reg) => handle_cmd_reg(cmd_reg, opts),
+        CmdEnum::UpdateLed(cmd_update_led) => handle_cmd_update_led(cmd_update_led, opts),
+        CmdEnum::SystemdUnit(cmd_systemd_unit) => handle_cmd_systemd_unit(cmd_systemd_unit),
+        CmdEnum::GenUdev(cmd_gen_udev) => handle_cmd_gen_udev(cmd_gen_udev),
+        CmdEnum::Completions(cmd_completions) => handle_cmd_completions(cmd_completions),
+        CmdEnum::Selftest(cmd_selftest) => handle_cmd_selftest(cmd_selftest, opts),
+        CmdEnum::Identify(cmd_identify) => handle_cmd_identify(cmd_identify, opts),
+        CmdEnum::Watch(cmd_watch) => handle_cmd_watch(cmd_watch, opts),
+        CmdEnum::Dump(cmd_dump) => handle_cmd_dump(cmd_dump, opts),
+        CmdEnum::Diff(cmd_diff) => handle_cmd_diff(cmd_diff, opts),
+        CmdEnum::Versions(_) => handle_cmd_versions(),
+        #[cfg(feature = "tui")]
+        CmdEnum::Dashboard(cmd_dashboard) => dashboard::handle_cmd_dashboard(cmd_dashboard, opts),
+        #[cfg(feature = "toml")]
+        CmdEnum::Apply(cmd_apply) => config::handle_cmd_apply(cmd_apply, opts),
     };
     if let Err(e) = res {
         eprintln!("Error: {}", e);
-        std::process::exit(1);
+        std::process::exit(e.exit_code());
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmd_set(args: &[&str]) -> CmdSet {
+        CmdSet::from_args(&["set"], args).unwrap()
+    }
+
+    #[test]
+    fn led_mode_range() {
+        assert_eq!(cmd_set(&["--led-mode", "7"]).led_mode, Some(ArgLedMode(7)));
+        for mode in ["8", "255", "-1", "x"] {
+            let err = CmdSet::from_args(&["set"], &["--led-mode", mode]).unwrap_err();
+            assert!(
+                err.output.contains("--led-mode must be 0-7"),
+                "{}",
+                err.output
+            );
+        }
+    }
+
+    #[test]
+    fn clamp_reserved_masks_value() {
+        assert_eq!(
+            clamp_reserved(0x0377_0087),
+            (
+                0x0007_0087,
+                Some("dropped reserved bits 0x03700000 from raw value 0x03770087".to_string())
+            )
+        );
+        assert_eq!(clamp_reserved(0xe0087), (0xe0087, None));
+    }
+
+    #[test]
+    fn preserve_reserved_merges_known_fields() {
+        let mut config = led::LedGlobalConfig::from_raw(0x0500_0087);
+        let current = led::LedGlobalConfig::from_raw(0xa00e_1234);
+        preserve_reserved(&mut config, &current);
+        // known fields from the new value, reserved bits from the device
+        assert_eq!(config.to_raw(), 0xa000_0087);
+    }
+
+    #[test]
+    fn preserve_reserved_conflicts_with_clamp() {
+        let cmd = cmd_set(&[
+            "--raw",
+            "0xe0087",
+            "--clamp-reserved",
+            "--preserve-reserved",
+        ]);
+        let err = handle_cmd_set(cmd, &Options::default()).unwrap_err();
+        assert!(matches!(err, Error::InvalidArgs(msg) if msg.contains("--preserve-reserved")));
+    }
+
+    #[test]
+    fn reverse_all_overridden_per_led() {
+        let mut config = led::LedGlobalConfig::from_raw(0xe0087);
+        cmd_set(&["--reverse-all", "true", "--led1-reverse", "false"])
+            .update_led_config(&mut config, None);
+        assert!(config.led_0.high_active);
+        assert!(!config.led_1.high_active);
+        assert!(config.led_2.high_active);
+
+        let mut config = led::LedGlobalConfig::from_raw(0xe7087);
+        cmd_set(&["--reverse-all", "false", "--led2-reverse", "true"])
+            .update_led_config(&mut config, None);
+        assert!(!config.led_0.high_active);
+        assert!(!config.led_1.high_active);
+        assert!(config.led_2.high_active);
+    }
+
+    #[test]
+    fn led_options_apply_to_own_led() {
+        let cmd = cmd_set(&[
+            "--led0-link",
+            "10",
+            "--led0-act",
+            "false",
+            "--led0-reverse",
+            "true",
+            "--led1-link",
+            "100",
+            "--led1-act",
+            "true",
+            "--led1-reverse",
+            "false",
+            "--led2-link",
+            "10,1000",
+            "--led2-act",
+            "true",
+            "--led2-reverse",
+            "true",
+        ]);
+        let mut config = led::LedGlobalConfig::from_raw(0);
+        cmd.update_led_config(&mut config, None);
+
+        assert_eq!(
+            config.led_0,
+            led::LedConfig {
+                link10: true,
+                link100: false,
+                link1000: false,
+                activity: false,
+                high_active: true,
+            }
+        );
+        assert_eq!(
+            config.led_1,
+            led::LedConfig {
+                link10: false,
+                link100: true,
+                link1000: false,
+                activity: true,
+                high_active: false,
+            }
+        );
+        assert_eq!(
+            config.led_2,
+            led::LedConfig {
+                link10: true,
+                link100: false,
+                link1000: true,
+                activity: true,
+                high_active: true,
+            }
+        );
+        // LED select nibbles 0x1, 0xa and 0xd, high active of LED 0 and 2
+        assert_eq!(config.to_raw(), 0x5da1);
+        assert_eq!(led::LedGlobalConfig::from_raw(0x5da1), config);
+    }
+
+    #[test]
+    fn diff_selectors_from_filter() {
+        let diff = |args: &[&str]| CmdDiff::from_args(&["diff"], args).unwrap();
+        assert!(!diff(&["--raw-b", "0"]).has_selectors());
+        for selector in [
+            &["--device", "1:5"][..],
+            &["--product", "0bda:8153"],
+            &["--usb-path", "1-1.*"],
+            &["--exclude", "1:5"],
+            &["--match", "any"],
+            &["--once-per-bus"],
+            &["--strict"],
+        ] {
+            let cmd = diff(&[&["--raw-b", "0"], selector].concat());
+            assert!(cmd.has_selectors(), "{:?}", selector);
+        }
+    }
+
+    #[test]
+    fn delay_ms_requires_all() {
+        let err = handle_cmd_set(cmd_set(&["--delay-ms", "100"]), &Options::default()).unwrap_err();
+        assert!(matches!(err, Error::InvalidArgs(msg) if msg.contains("--delay-ms")));
+    }
+
+    #[test]
+    fn apply_and_watch_args() {
+        for (args, msg) in [
+            (&["--apply-and-watch", "--dry"][..], "--apply-and-watch"),
+            (&["--apply-and-watch", "--all"], "--apply-and-watch"),
+            (&["--interval-ms", "500"], "--interval-ms"),
+        ] {
+            let err = handle_cmd_set(cmd_set(args), &Options::default()).unwrap_err();
+            assert!(
+                matches!(err, Error::InvalidArgs(m) if m.contains(msg)),
+                "{:?}",
+                args
+            );
+        }
+    }
+
+    #[test]
+    fn show_args_checked_before_dump() {
+        let path =
+            std::env::temp_dir().join(format!("rtl8152-led-ctrl-{}.txt", std::process::id()));
+        let args = [
+            "--json",
+            "--list-unsupported",
+            "--dump",
+            path.to_str().unwrap(),
+        ];
+        let cmd = CmdShow::from_args(&["show"], &args).unwrap();
+        let err = handle_cmd_show(cmd, &Options::default()).unwrap_err();
+        assert!(matches!(err, Error::InvalidArgs(m) if m.contains("--list-unsupported")));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn arg_link_into_led_links() {
+        let links = |s: &str| led::LedLinks::from(s.parse::<ArgLink>().unwrap());
+        for s in ["", "0"] {
+            assert_eq!(links(s), led::LedLinks::default(), "{:?}", s);
+        }
+        assert_eq!(
+            links("100"),
+            led::LedLinks {
+                link10: false,
+                link100: true,
+                link1000: false,
+            }
+        );
+        assert_eq!(
+            links("10,100,1000"),
+            led::LedLinks {
+                link10: true,
+                link100: true,
+                link1000: true,
+            }
+        );
+    }
+}
@@ -0,0 +1,235 @@
+// SPDX-FileCopyrightText: 2024 Huang-Huang Bao
+// SPDX-License-Identifier: MIT
+// SPDX-License-Identifier: Apache-2.0
+use std::path::{Path, PathBuf};
+
+use crate::led::{LedConfig, LedGlobalConfig};
+use crate::result::{Error, Result, ResultExt};
+
+/// Named LED configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preset {
+    pub name: String,
+    pub config: LedGlobalConfig,
+}
+
+/// Built-in presets:
+///
+/// - `default`: opinionated default, LED 0 lights on link of all speeds and
+///   LED 1 blinks on activity
+/// - `dark`: all LEDs off
+/// - `link-act`: LED 0 lights on link of all speeds and blinks on activity,
+///   other LEDs off
+/// - `speed`: LED 0, 1 and 2 light on link of 1000Mbps, 100Mbps and 10Mbps
+///   respectively and blink on activity
+pub fn builtin_presets() -> Vec<Preset> {
+    let default = LedGlobalConfig::opinionated_default();
+
+    let mut dark = default.clone();
+    dark.led_0 = LedConfig::default();
+    dark.led_1 = LedConfig::default();
+    dark.led_2 = LedConfig::default();
+
+    let mut link_act = dark.clone();
+    link_act.led_0 = LedConfig {
+        link10: true,
+        link100: true,
+        link1000: true,
+        activity: true,
+        ..Default::default()
+    };
+
+    let mut speed = dark.clone();
+    speed.led_0 = LedConfig {
+        link1000: true,
+        activity: true,
+        ..Default::default()
+    };
+    speed.led_1 = LedConfig {
+        link100: true,
+        activity: true,
+        ..Default::default()
+    };
+    speed.led_2 = LedConfig {
+        link10: true,
+        activity: true,
+        ..Default::default()
+    };
+
+    [
+        ("default", default),
+        ("dark", dark),
+        ("link-act", link_act),
+        ("speed", speed),
+    ]
+    .into_iter()
+    .map(|(name, config)| Preset {
+        name: name.to_string(),
+        config,
+    })
+    .collect()
+}
+
+/// Parses presets file, each line is in form of `name = raw_value`, e.g.
+/// `office = 0xe0087`, lines starting with `#` are comments.
+pub fn parse_presets(content: &str) -> Result<Vec<Preset>> {
+    let mut res = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((name, value)) = line.split_once('=') else {
+            return Err(Error::Preset(format!(
+                "line {}: expect `name = raw_value`",
+                i + 1
+            )));
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(Error::Preset(format!("line {}: empty preset name", i + 1)));
+        }
+        let Ok(value) = parse_int::parse::<u32>(value.trim()) else {
+            return Err(Error::Preset(format!(
+                "line {}: invalid raw value of preset {}",
+                i + 1,
+                name
+            )));
+        };
+        res.push(Preset {
+            name: name.to_string(),
+            config: LedGlobalConfig::from_raw(value),
+        });
+    }
+    Ok(res)
+}
+
+/// Loads built-in presets and presets from file, presets in file override
+/// built-in ones of the same name.
+pub fn load_presets(path: Option<&Path>) -> Result<Vec<Preset>> {
+    let mut presets = builtin_presets();
+    if let Some(path) = path {
+        let content = std::fs::read_to_string(path).context("reading presets file")?;
+        for preset in parse_presets(&content).context("parsing presets file")? {
+            presets.retain(|p| p.name != preset.name);
+            presets.push(preset);
+        }
+    }
+    Ok(presets)
+}
+
+/// Finds preset by name
+pub fn find<'a>(presets: &'a [Preset], name: &str) -> Result<&'a Preset> {
+    presets.iter().find(|p| p.name == name).ok_or_else(|| {
+        let names = presets.iter().map(|p| p.name.as_str()).collect::<Vec<_>>();
+        Error::Preset(format!(
+            "unknown preset {}, available presets: {}",
+            name,
+            names.join(", ")
+        ))
+    })
+}
+
+/// Finds preset having the same LED fields as config, ignoring reserved bits
+pub fn find_matching<'a>(presets: &'a [Preset], config: &LedGlobalConfig) -> Option<&'a Preset> {
+    presets.iter().find(|p| p.config.fields_eq(config))
+}
+
+/// Resolves per-device config file in `dir` for device of `stable_id`.
+///
+/// Lookup order is `<stable_id>.conf` (with "/" replaced by "_"), then
+/// `default.conf`. A config file holds either a raw LED register value or a
+/// preset name, lines starting with `#` are comments. Returns `None` if
+/// neither file exists.
+pub fn load_device_config(
+    dir: &Path,
+    stable_id: &str,
+    presets: &[Preset],
+) -> Result<Option<(PathBuf, LedGlobalConfig)>> {
+    let candidates = [
+        format!("{}.conf", stable_id.replace('/', "_")),
+        "default.conf".to_string(),
+    ];
+    for name in candidates {
+        let path = dir.join(name);
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).context("reading device config file"),
+        };
+        let Some(value) = content
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+        else {
+            return Err(Error::Preset(format!("{}: empty config", path.display())));
+        };
+        let config = match parse_int::parse::<u32>(value) {
+            Ok(raw) => LedGlobalConfig::from_raw(raw),
+            Err(_) => find(presets, value)?.config.clone(),
+        };
+        return Ok(Some((path, config)));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_presets_lines() {
+        let presets = parse_presets(
+            "# comment\n\
+             office = 0xe0087\n\
+             \n\
+             \x20 dark2=0 \n\
+             dec = 917639\n",
+        )
+        .unwrap();
+        let parsed = presets
+            .iter()
+            .map(|p| (p.name.as_str(), p.config.to_raw()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            parsed,
+            [("office", 0xe0087), ("dark2", 0), ("dec", 0xe0087)]
+        );
+    }
+
+    #[test]
+    fn parse_presets_invalid() {
+        for content in ["office 0xe0087", "office = zzz", "office =", "= 0xe0087"] {
+            assert!(
+                matches!(parse_presets(content), Err(Error::Preset(msg)) if msg.starts_with("line 1:")),
+                "{}",
+                content
+            );
+        }
+        assert!(matches!(
+            parse_presets("# ok\n = 0xe0087"),
+            Err(Error::Preset(msg)) if msg == "line 2: empty preset name"
+        ));
+    }
+
+    #[test]
+    fn builtin_presets_values() {
+        let presets = builtin_presets();
+        for (name, raw) in [
+            ("default", 0xe0087),
+            ("dark", 0xe0000),
+            ("link-act", 0xe000f),
+            ("speed", 0xe09ac),
+        ] {
+            assert_eq!(
+                find(&presets, name).unwrap().config.to_raw(),
+                raw,
+                "{}",
+                name
+            );
+        }
+        assert!(find(&presets, "nope").is_err());
+        let config = LedGlobalConfig::from_raw(0xfff0_0000 | 0xe000f);
+        assert_eq!(find_matching(&presets, &config).unwrap().name, "link-act");
+    }
+}
@@ -0,0 +1,115 @@
+// SPDX-FileCopyrightText: 2024 Huang-Huang Bao
+// SPDX-License-Identifier: MIT
+// SPDX-License-Identifier: Apache-2.0
+use std::path::Path;
+
+use crate::result::{Error, Result, ResultExt};
+
+/// Named set of `set` arguments, bundling device selector and LED options
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+/// Parses profiles file, each line is in form of `name = arguments`, e.g.
+/// `office = --product 0bda:8153 --led0-link 1000 --led0-act true`,
+/// arguments are separated by whitespace, lines starting with `#` are
+/// comments. Later profiles override earlier ones of the same name.
+pub fn parse_profiles(content: &str) -> Result<Vec<Profile>> {
+    let mut res: Vec<Profile> = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((name, args)) = line.split_once('=') else {
+            return Err(Error::Profile(format!(
+                "line {}: expect `name = arguments`",
+                i + 1
+            )));
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(Error::Profile(format!(
+                "line {}: empty profile name",
+                i + 1
+            )));
+        }
+        res.retain(|p| p.name != name);
+        res.push(Profile {
+            name: name.to_string(),
+            args: args.split_whitespace().map(str::to_string).collect(),
+        });
+    }
+    Ok(res)
+}
+
+/// Loads profiles from file
+pub fn load_profiles(path: &Path) -> Result<Vec<Profile>> {
+    let content = std::fs::read_to_string(path).context("reading profiles file")?;
+    parse_profiles(&content).context("parsing profiles file")
+}
+
+/// Finds profile by name
+pub fn find<'a>(profiles: &'a [Profile], name: &str) -> Result<&'a Profile> {
+    profiles.iter().find(|p| p.name == name).ok_or_else(|| {
+        let names = profiles.iter().map(|p| p.name.as_str()).collect::<Vec<_>>();
+        Error::Profile(format!(
+            "unknown profile {}, available profiles: {}",
+            name,
+            if names.is_empty() {
+                "none".to_string()
+            } else {
+                names.join(", ")
+            }
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_profiles_lines() {
+        let profiles = parse_profiles(
+            "# comment\n\
+             office = --product 0bda:8153  --preset link-act\n\
+             desk = --usb-path 1-1.*\n\
+             office = --device 1:7\n",
+        )
+        .unwrap();
+        assert_eq!(
+            profiles,
+            [
+                Profile {
+                    name: "desk".to_string(),
+                    args: vec!["--usb-path".to_string(), "1-1.*".to_string()],
+                },
+                Profile {
+                    name: "office".to_string(),
+                    args: vec!["--device".to_string(), "1:7".to_string()],
+                },
+            ]
+        );
+        assert_eq!(find(&profiles, "desk").unwrap().name, "desk");
+        assert!(
+            matches!(find(&profiles, "home"), Err(Error::Profile(msg)) if msg.contains("desk, office"))
+        );
+    }
+
+    #[test]
+    fn parse_profiles_invalid() {
+        for (content, error) in [
+            ("office", "line 1: expect `name = arguments`"),
+            ("# ok\n = --device 1:7", "line 2: empty profile name"),
+        ] {
+            assert!(
+                matches!(parse_profiles(content), Err(Error::Profile(msg)) if msg == error),
+                "{}",
+                content
+            );
+        }
+    }
+}
@@ -3,15 +3,55 @@
 // SPDX-License-Identifier: Apache-2.0
 use std::fmt;
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug)]
 pub enum Error {
     Parse,
+    InvalidArgs(&'static str),
     UnknownDevice,
     NotExist,
-    Align,
-    Bound,
-    Partial,
+    Align {
+        required: usize,
+        offset: u16,
+        len: usize,
+    },
+    Bound {
+        offset: u16,
+        len: usize,
+    },
+    Partial {
+        expected: usize,
+        actual: usize,
+    },
+    ByteMask(u8),
+    Unsupported,
     Usb(rusb::Error),
+    Io(std::io::Error),
+    Preset(String),
+    Profile(String),
+    #[cfg(feature = "toml")]
+    Config(String),
+    Precondition {
+        expected: u32,
+        actual: u32,
+    },
+    ConfirmMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    VerifyMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    SuspectRead(u32),
+    SomeFailed {
+        failed: usize,
+        total: usize,
+    },
+    Detach {
+        interface: u8,
+        source: rusb::Error,
+    },
+    Context(&'static str, Box<Error>),
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -20,18 +60,134 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Parse => f.write_str("failed to parse"),
+            Self::InvalidArgs(msg) => f.write_str(msg),
             Self::UnknownDevice => f.write_str("unknown device"),
             Self::NotExist => f.write_str("device not exist"),
-            Self::Align => f.write_str("offset or data not aligned"),
-            Self::Bound => f.write_str("out of bound"),
-            Self::Partial => f.write_str("partial read/write"),
+            Self::Align {
+                required,
+                offset,
+                len,
+            } => write!(
+                f,
+                "access of {} bytes at 0x{:04x} not aligned to {} ({} bytes)",
+                len,
+                offset,
+                if *required == 2 { "word" } else { "dword" },
+                required
+            ),
+            Self::Bound { offset, len } => write!(
+                f,
+                "access of {} bytes at 0x{:04x} out of bound of register space",
+                len, offset
+            ),
+            Self::Partial { expected, actual } => write!(
+                f,
+                "partial read/write, transferred {} of {} bytes",
+                actual, expected
+            ),
+            Self::Unsupported => f.write_str("not supported by this device or firmware"),
+            Self::ByteMask(mask) => write!(f, "illegal byte enable mask 0x{:02x}", mask),
             Self::Usb(e) => e.fmt(f),
+            Self::Io(e) => e.fmt(f),
+            Self::Preset(msg) => f.write_str(msg),
+            Self::Profile(msg) => f.write_str(msg),
+            #[cfg(feature = "toml")]
+            Self::Config(msg) => f.write_str(msg),
+            Self::Precondition { expected, actual } => write!(
+                f,
+                "register value 0x{:05x} differs from expected 0x{:05x}",
+                actual, expected
+            ),
+            Self::ConfirmMismatch { expected, actual } => write!(
+                f,
+                "register value 0x{:05x} after write differs from confirmed 0x{:05x}",
+                actual, expected
+            ),
+            Self::VerifyMismatch { expected, actual } => write!(
+                f,
+                "register value 0x{:05x} differs from written 0x{:05x} after retries",
+                actual, expected
+            ),
+            Self::SuspectRead(value) => write!(
+                f,
+                "suspect register value 0x{:08x}, the device may be disconnected or accessed through a wrong interface, pass --force to accept it",
+                value
+            ),
+            Self::SomeFailed { failed, total } => write!(
+                f,
+                "{} of {} devices failed, {} succeeded",
+                failed,
+                total,
+                total - failed
+            ),
+            Self::Detach {
+                interface,
+                source: rusb::Error::Access,
+            } => write!(
+                f,
+                "permission denied detaching kernel driver from interface {}, \
+                 run as root or grant write access to the USB device",
+                interface
+            ),
+            Self::Detach {
+                source: rusb::Error::NotSupported,
+                ..
+            } => f.write_str("detaching kernel driver is not supported on this platform"),
+            Self::Detach { interface, source } => write!(
+                f,
+                "failed to detach kernel driver from interface {}: {}",
+                interface, source
+            ),
+            Self::Context(what, e) => write!(f, "{}: {}", what, e),
         }
     }
 }
 
+impl Error {
+    /// Process exit code for the error, 3 for failed precondition of
+    /// `set --apply-if-changed-from`, 4 for failed `set --confirm-value`,
+    /// 1 for others
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Precondition { .. } => 3,
+            Self::ConfirmMismatch { .. } => 4,
+            Self::Context(_, e) => e.exit_code(),
+            _ => 1,
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Usb(e) => Some(e),
+            Self::Io(e) => Some(e),
+            Self::Detach { source, .. } => Some(source),
+            Self::Context(_, e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// Tags errors with the operation being performed
+pub trait ResultExt<T> {
+    fn context(self, what: &'static str) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> ResultExt<T> for Result<T, E> {
+    fn context(self, what: &'static str) -> Result<T> {
+        self.map_err(|e| Error::Context(what, Box::new(e.into())))
+    }
+}
+
 impl From<rusb::Error> for Error {
     fn from(value: rusb::Error) -> Self {
         Self::Usb(value)
     }
 }
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
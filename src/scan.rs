@@ -0,0 +1,682 @@
+// SPDX-FileCopyrightText: 2024 Huang-Huang Bao
+// SPDX-License-Identifier: MIT
+// SPDX-License-Identifier: Apache-2.0
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::device::{self, CtrlDevice, Version};
+use crate::led::{LedFeatureConfig, LedGlobalConfig};
+use crate::result::{Error, Result, ResultExt};
+
+const VID_REALTEK: u16 = 0x0bda;
+const VID_MICROSOFT: u16 = 0x045e;
+const VID_SAMSUNG: u16 = 0x0419;
+const VID_LENOVO: u16 = 0x17ef;
+const VID_LINKSYS: u16 = 0x13b1;
+const VID_NVIDIA: u16 = 0x0955;
+const VID_TPLINK: u16 = 0x2357;
+const VID_DLINK: u16 = 0x2001;
+const VID_ASUS: u16 = 0x0b05;
+
+/// Interface classes of NICs, devices without interface of these classes are
+/// not treated as NICs by default
+pub const NETWORK_CLASSES: &[u8] = &[
+    0x02, // CDC communications
+    0x0a, // CDC data
+    0xff, // vendor specific
+];
+
+/// Vendor and product IDs of supported devices
+pub const RTL8152_DEVICE_VID_PIDS: &[(u16, u16)] = &[
+    (VID_REALTEK, 0x8050),
+    (VID_REALTEK, 0x8053),
+    (VID_REALTEK, 0x8152),
+    (VID_REALTEK, 0x8153),
+    (VID_REALTEK, 0x8155),
+    (VID_REALTEK, 0x8156),
+    (VID_MICROSOFT, 0x07ab),
+    (VID_MICROSOFT, 0x07c6),
+    (VID_MICROSOFT, 0x0927),
+    (VID_MICROSOFT, 0x0c5e),
+    (VID_SAMSUNG, 0xa101),
+    (VID_LENOVO, 0x304f),
+    (VID_LENOVO, 0x3054),
+    (VID_LENOVO, 0x3062),
+    (VID_LENOVO, 0x3069),
+    (VID_LENOVO, 0x3082),
+    (VID_LENOVO, 0x7205),
+    (VID_LENOVO, 0x720c),
+    (VID_LENOVO, 0x7214),
+    (VID_LENOVO, 0x721e),
+    (VID_LENOVO, 0xa387),
+    (VID_LINKSYS, 0x0041),
+    (VID_NVIDIA, 0x09ff),
+    (VID_TPLINK, 0x0601),
+    (VID_DLINK, 0xb301),
+    (VID_ASUS, 0x1976),
+];
+
+/// Device address on USB bus, parsed from "bus_num:dev_num"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceAddr {
+    pub bus: u8,
+    pub addr: u8,
+}
+
+/// USB vendor and product IDs, parsed from hexadecimal "vid:pid"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProductId {
+    pub vid: u16,
+    pub pid: u16,
+}
+
+/// How to combine selectors of [`Filter`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MatchMode {
+    /// Select devices matching all selectors supplied
+    #[default]
+    All,
+    /// Select devices matching any selector supplied
+    Any,
+}
+
+impl FromStr for DeviceAddr {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let Some((bus, addr)) = s.split_once(':') else {
+            return Err("invalid format, supply bus_num:dev_num instead".to_string());
+        };
+        let Ok(bus) = u8::from_str(bus) else {
+            return Err("failed to parse bus number".to_string());
+        };
+        let Ok(addr) = u8::from_str(addr) else {
+            return Err("failed to parse device number".to_string());
+        };
+
+        Ok(DeviceAddr { bus, addr })
+    }
+}
+
+impl FromStr for ProductId {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let Some((vid, pid)) = s.split_once(':') else {
+            return Err("invalid format, supply vid:pid instead".to_string());
+        };
+        let Ok(vid) = u16::from_str_radix(vid, 16) else {
+            return Err("failed to parse vendor ID".to_string());
+        };
+        let Ok(pid) = u16::from_str_radix(pid, 16) else {
+            return Err("failed to parse product ID".to_string());
+        };
+
+        Ok(ProductId { vid, pid })
+    }
+}
+
+impl FromStr for MatchMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "all" => Ok(Self::All),
+            "any" => Ok(Self::Any),
+            unknown => Err(format!("invalid match mode {}, either all or any", unknown)),
+        }
+    }
+}
+
+/// Selectors of devices, all devices supported are selected if none of
+/// `devices`, `product`, `name` and `usb_path` is supplied
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    pub devices: Option<Vec<DeviceAddr>>,
+    pub product: Option<ProductId>,
+    /// Case-insensitive substring of manufacturer or product name, matching by
+    /// name opens every candidate
+    pub name: Option<String>,
+    /// USB port path glob, see [`device::usb_path`]
+    pub usb_path: Option<String>,
+    /// Devices never selected, excludes override other selectors
+    pub exclude: Vec<DeviceAddr>,
+    pub exclude_product: Vec<ProductId>,
+    pub match_mode: MatchMode,
+    /// Fail on devices failed to enumerate instead of skipping them
+    pub strict: bool,
+    /// Only keep the first matched device on each USB bus, in order of device
+    /// number
+    pub once_per_bus: bool,
+}
+
+impl Filter {
+    /// Whether the device is excluded by `exclude` or `exclude_product`
+    fn excludes(&self, addr: DeviceAddr, product: ProductId) -> bool {
+        self.exclude.contains(&addr) || self.exclude_product.contains(&product)
+    }
+
+    /// Whether selectors other than `name` select the device, `usb_path` is
+    /// required if `self.usb_path` is supplied. Returns `None` if the
+    /// result depends on `name`, which requires opening the device.
+    fn selects(
+        &self,
+        addr: DeviceAddr,
+        product: ProductId,
+        usb_path: Option<&str>,
+    ) -> Option<bool> {
+        let match_all = self.match_mode == MatchMode::All;
+        let predicates = [
+            self.devices.as_ref().map(|devices| devices.contains(&addr)),
+            self.product.map(|p| p == product),
+            self.usb_path.as_ref().map(|pattern| {
+                usb_path.is_some_and(|path| glob_matches(pattern.as_bytes(), path.as_bytes()))
+            }),
+        ];
+        let mut supplied = predicates.iter().flatten().peekable();
+        let none_supplied = supplied.peek().is_none();
+        let selected = if match_all {
+            supplied.all(|&m| m)
+        } else {
+            supplied.any(|&m| m)
+        };
+
+        if self.name.is_some() {
+            (selected != match_all).then_some(selected)
+        } else {
+            // no selector supplied
+            Some(selected || none_supplied)
+        }
+    }
+}
+
+/// Options of enumerating and opening devices
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Interface to claim before accessing registers
+    pub interface: Option<u8>,
+    /// Only treat devices having interface of given classes as NICs, empty
+    /// for any class
+    pub require_class: Vec<u8>,
+    /// Print diagnostic messages to stderr if greater than 0
+    pub verbose: u8,
+    /// Treat devices as given chip version instead of probing it
+    pub assume_version: Option<Version>,
+    /// Do not reject devices reporting unknown chip version
+    pub no_version_check: bool,
+    /// Time to wait after opening a device before accessing its registers
+    pub delay_after_open: Duration,
+    /// Timeout of each control transfer
+    pub timeout: Duration,
+    /// Detach kernel drivers while accessing registers
+    pub detach: bool,
+    /// Times to retry a control transfer failed with transient errors
+    pub retries: u32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            interface: None,
+            require_class: NETWORK_CLASSES.to_vec(),
+            verbose: 0,
+            assume_version: None,
+            no_version_check: false,
+            delay_after_open: Duration::ZERO,
+            timeout: device::DEFAULT_TIMEOUT,
+            detach: false,
+            retries: device::DEFAULT_RETRIES,
+        }
+    }
+}
+
+/// Information of a matched device and its LED configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub device: rusb::Device<rusb::GlobalContext>,
+    pub bus: u8,
+    pub addr: u8,
+    pub vid: u16,
+    pub pid: u16,
+    pub manufacturer: String,
+    pub product: String,
+    pub serial: String,
+    /// See [`CtrlDevice::stable_id`]
+    pub stable_id: String,
+    pub version: Version,
+    /// See [`CtrlDevice::mac_address`]
+    pub mac: Option<[u8; 6]>,
+    pub led_config: LedGlobalConfig,
+    /// `None` if the chip has no LED feature register
+    pub feature_config: Option<LedFeatureConfig>,
+}
+
+/// Opens every device matching `filter` and reads its information, devices
+/// are in order of (bus, addr). LED register values likely read from a
+/// disconnected device are rejected unless `force`, see
+/// [`LedGlobalConfig::read_from`]. Devices failed to open or read are
+/// skipped, unless `filter.strict`.
+pub fn scan(filter: &Filter, opts: &Options, force: bool) -> Result<Vec<DeviceInfo>> {
+    let devices = filter_devices(filter, opts, false).context("enumerating devices")?;
+    let mut res = Vec::new();
+    for device in devices {
+        match scan_device(&device, opts, force) {
+            Ok(info) => res.push(info),
+            Err(e) if filter.strict => return Err(e),
+            Err(e) => {
+                if opts.verbose > 0 {
+                    eprintln!(
+                        "skipping Bus({:03}:{:03}): {}",
+                        device.bus_number(),
+                        device.address(),
+                        e
+                    );
+                }
+            }
+        }
+    }
+    Ok(res)
+}
+
+fn scan_device(
+    device: &rusb::Device<rusb::GlobalContext>,
+    opts: &Options,
+    force: bool,
+) -> Result<DeviceInfo> {
+    let desc = device.device_descriptor()?;
+    let ctrl = open(device, opts).context("opening device")?;
+    let handle = ctrl.handle();
+    let version = ctrl.version()?;
+    let led_config = if force {
+        LedGlobalConfig::read_from_unchecked(&ctrl)
+    } else {
+        LedGlobalConfig::read_from(&ctrl)
+    };
+    let feature_config = if version.capabilities().led_feature {
+        let config = LedFeatureConfig::read_from(&ctrl);
+        Some(config.context("reading LED feature register")?)
+    } else {
+        None
+    };
+    Ok(DeviceInfo {
+        bus: device.bus_number(),
+        addr: device.address(),
+        vid: desc.vendor_id(),
+        pid: desc.product_id(),
+        manufacturer: handle.read_manufacturer_string_ascii(&desc)?,
+        product: handle.read_product_string_ascii(&desc)?,
+        // serial number is optional
+        serial: handle
+            .read_serial_number_string_ascii(&desc)
+            .unwrap_or_default(),
+        stable_id: ctrl.stable_id()?,
+        version,
+        mac: ctrl.mac_address()?,
+        led_config: led_config.context("reading LED register")?,
+        feature_config,
+        device: device.clone(),
+    })
+}
+
+/// Enumerates devices matching `filter`, in order of (bus, addr), only the
+/// first one if `once`
+pub fn filter_devices(
+    filter: &Filter,
+    opts: &Options,
+    once: bool,
+) -> Result<Vec<rusb::Device<rusb::GlobalContext>>> {
+    let match_all = filter.match_mode == MatchMode::All;
+    let skip = |device: &rusb::Device<rusb::GlobalContext>, e: &dyn std::fmt::Display| {
+        if opts.verbose > 0 {
+            eprintln!(
+                "skipping Bus({:03}:{:03}): {}",
+                device.bus_number(),
+                device.address(),
+                e
+            );
+        }
+    };
+
+    let mut res = Vec::new();
+    for device in rusb::devices()?.iter() {
+        let addr = DeviceAddr {
+            bus: device.bus_number(),
+            addr: device.address(),
+        };
+        if match_all && filter.devices.as_ref().is_some_and(|d| !d.contains(&addr)) {
+            continue;
+        }
+
+        let device_desc = match device.device_descriptor() {
+            Ok(desc) => desc,
+            Err(e) if !filter.strict => {
+                skip(&device, &e);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let product = ProductId {
+            vid: device_desc.vendor_id(),
+            pid: device_desc.product_id(),
+        };
+
+        if !RTL8152_DEVICE_VID_PIDS.contains(&(product.vid, product.pid)) {
+            continue;
+        }
+        if filter.excludes(addr, product) {
+            continue;
+        }
+
+        if !opts.require_class.is_empty() {
+            match device_has_class(&device, &opts.require_class) {
+                Ok(true) => {}
+                Ok(false) => {
+                    skip(&device, &"no network interface");
+                    continue;
+                }
+                Err(e) if !filter.strict => {
+                    skip(&device, &e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let usb_path = match &filter.usb_path {
+            Some(_) => match device::usb_path(&device) {
+                Ok(path) => Some(path),
+                Err(e) if !filter.strict => {
+                    skip(&device, &e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            },
+            None => None,
+        };
+        let selected = match filter.selects(addr, product, usb_path.as_deref()) {
+            Some(selected) => selected,
+            // only open the device for name if other selectors didn't decide
+            None => {
+                let name = filter.name.as_deref().unwrap_or_default();
+                match device_name_matches(&device, &device_desc, name) {
+                    Ok(name_matches) => name_matches,
+                    Err(e) if !filter.strict => {
+                        skip(&device, &e);
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        };
+        if !selected {
+            continue;
+        }
+
+        res.push(device);
+    }
+
+    order_devices(&mut res, filter.once_per_bus, |device| DeviceAddr {
+        bus: device.bus_number(),
+        addr: device.address(),
+    });
+    if once {
+        res.truncate(1);
+    }
+
+    if filter.name.is_some() && res.is_empty() {
+        return Err(Error::NotExist);
+    }
+
+    Ok(res)
+}
+
+/// Sorts devices by (bus, addr) as libusb enumeration order may vary between
+/// runs, then keeps only the first device on each bus if `once_per_bus`
+fn order_devices<T>(devices: &mut Vec<T>, once_per_bus: bool, addr: impl Fn(&T) -> DeviceAddr) {
+    devices.sort_by_key(|device| {
+        let DeviceAddr { bus, addr } = addr(device);
+        (bus, addr)
+    });
+    if once_per_bus {
+        devices.dedup_by_key(|device| addr(device).bus);
+    }
+}
+
+/// Matches `text` against glob `pattern`, "*" matches any characters and "?"
+/// matches a single character
+fn glob_matches(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // position of last "*" in pattern and of text it is matched up to, on
+    // mismatch let the star consume one more character instead of
+    // backtracking over every star
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+fn device_has_class(device: &rusb::Device<rusb::GlobalContext>, classes: &[u8]) -> Result<bool> {
+    let config = match device.active_config_descriptor() {
+        Ok(config) => config,
+        // device not configured
+        Err(rusb::Error::NotFound) => device.config_descriptor(0)?,
+        Err(e) => return Err(e.into()),
+    };
+    let res = config.interfaces().any(|interface| {
+        interface
+            .descriptors()
+            .any(|desc| classes.contains(&desc.class_code()))
+    });
+    Ok(res)
+}
+
+fn device_name_matches(
+    device: &rusb::Device<rusb::GlobalContext>,
+    desc: &rusb::DeviceDescriptor,
+    name: &str,
+) -> Result<bool> {
+    let handle = device.open()?;
+    let name = name.to_ascii_lowercase();
+    let vendor = handle.read_manufacturer_string_ascii(desc)?;
+    let product = handle.read_product_string_ascii(desc)?;
+
+    Ok(vendor.to_ascii_lowercase().contains(&name) || product.to_ascii_lowercase().contains(&name))
+}
+
+/// Opens device and probes its chip version per `opts`, kernel drivers
+/// detached are reattached on drop of the returned controller
+pub fn open(device: &rusb::Device<rusb::GlobalContext>, opts: &Options) -> Result<CtrlDevice> {
+    let handle = device.open()?;
+    let detached = if opts.detach {
+        let interfaces = match opts.interface {
+            Some(interface) => vec![interface],
+            None => device
+                .active_config_descriptor()?
+                .interfaces()
+                .map(|interface| interface.number())
+                .collect(),
+        };
+        device::detach_kernel_drivers(&handle, &interfaces)?
+    } else {
+        Vec::new()
+    };
+    if let Some(interface) = opts.interface {
+        if let Err(e) = handle.claim_interface(interface) {
+            device::attach_kernel_drivers(&handle, &detached);
+            return Err(e.into());
+        }
+    }
+    if !opts.delay_after_open.is_zero() {
+        std::thread::sleep(opts.delay_after_open);
+    }
+    let mut ctrl = match opts.assume_version {
+        Some(version) => {
+            eprintln!("Note: assuming version {:?}, not probed", version);
+            CtrlDevice::with_version(handle, version)
+        }
+        None => CtrlDevice::new_unchecked(handle),
+    };
+    ctrl.set_timeout(opts.timeout);
+    ctrl.set_retries(opts.retries);
+    ctrl.reattach_on_drop(detached);
+    if let Some(interface) = opts.interface {
+        ctrl.release_on_drop(interface);
+    }
+    if opts.assume_version.is_none() {
+        if opts.no_version_check {
+            eprintln!(
+                "Warning: version check disabled, the device may not be RTL8152 series, \
+                 register layouts may differ"
+            );
+        } else {
+            ctrl.probe_version()?;
+        }
+    }
+    Ok(ctrl)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RTL8153: ProductId = ProductId {
+        vid: 0x0bda,
+        pid: 0x8153,
+    };
+    const RTL8156: ProductId = ProductId {
+        vid: 0x0bda,
+        pid: 0x8156,
+    };
+
+    fn addr(bus: u8, addr: u8) -> DeviceAddr {
+        DeviceAddr { bus, addr }
+    }
+
+    /// Whether `filter` selects the device, without matching by name
+    fn selected(filter: &Filter, addr: DeviceAddr, product: ProductId) -> bool {
+        !filter.excludes(addr, product) && filter.selects(addr, product, None) == Some(true)
+    }
+
+    #[test]
+    fn exclude_overrides_devices() {
+        let filter = Filter {
+            devices: Some(vec![addr(1, 2), addr(1, 3)]),
+            exclude: vec![addr(1, 3)],
+            ..Default::default()
+        };
+        assert!(selected(&filter, addr(1, 2), RTL8153));
+        assert!(!selected(&filter, addr(1, 3), RTL8153));
+        assert!(!selected(&filter, addr(1, 4), RTL8153));
+    }
+
+    #[test]
+    fn exclude_product_overrides_any_match() {
+        let filter = Filter {
+            devices: Some(vec![addr(2, 5)]),
+            product: Some(RTL8153),
+            exclude_product: vec![RTL8156],
+            match_mode: MatchMode::Any,
+            ..Default::default()
+        };
+        assert!(selected(&filter, addr(1, 2), RTL8153));
+        // selected by address, but excluded by product
+        assert!(!selected(&filter, addr(2, 5), RTL8156));
+    }
+
+    #[test]
+    fn exclude_without_selectors() {
+        let filter = Filter {
+            exclude: vec![addr(1, 2)],
+            ..Default::default()
+        };
+        assert!(!selected(&filter, addr(1, 2), RTL8153));
+        assert!(selected(&filter, addr(1, 3), RTL8153));
+        assert!(selected(&filter, addr(2, 2), RTL8156));
+    }
+
+    #[test]
+    fn once_per_bus_keeps_lowest_address() {
+        let mut devices = vec![addr(2, 9), addr(1, 7), addr(2, 3), addr(1, 4), addr(1, 5)];
+        order_devices(&mut devices, false, |&d| d);
+        assert_eq!(
+            devices,
+            [addr(1, 4), addr(1, 5), addr(1, 7), addr(2, 3), addr(2, 9)]
+        );
+        order_devices(&mut devices, true, |&d| d);
+        assert_eq!(devices, [addr(1, 4), addr(2, 3)]);
+    }
+
+    #[test]
+    fn name_decides_only_if_undecided() {
+        let filter = Filter {
+            product: Some(RTL8153),
+            name: Some("realtek".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(filter.selects(addr(1, 2), RTL8153, None), None);
+        assert_eq!(filter.selects(addr(1, 2), RTL8156, None), Some(false));
+    }
+
+    #[test]
+    fn glob_matching_paths() {
+        for (pattern, path) in [
+            ("1-1.2", "1-1.2"),
+            ("1-1.*", "1-1.2"),
+            ("1-1.*", "1-1.2.4"),
+            ("1-1.?", "1-1.3"),
+            ("*", ""),
+            ("*", "5-1"),
+            ("?-*.2", "3-1.4.2"),
+            ("**1", "1-1"),
+        ] {
+            assert!(
+                glob_matches(pattern.as_bytes(), path.as_bytes()),
+                "{} {}",
+                pattern,
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn glob_non_matching_paths() {
+        for (pattern, path) in [
+            ("1-1.2", "1-1.3"),
+            ("1-1.2", "1-1.2.4"),
+            ("1-1.*", "1-1"),
+            ("1-1.?", "1-1.12"),
+            ("1-1.?", "1-1."),
+            ("?", ""),
+            ("2-*", "1-2"),
+        ] {
+            assert!(
+                !glob_matches(pattern.as_bytes(), path.as_bytes()),
+                "{} {}",
+                pattern,
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn glob_many_stars_not_exponential() {
+        let pattern = "*1".repeat(30) + "*2";
+        let text = "1".repeat(100);
+        assert!(!glob_matches(pattern.as_bytes(), text.as_bytes()));
+    }
+}